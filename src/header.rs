@@ -12,6 +12,10 @@ pub enum Opcode {
     InverseQuery,
     /// Status request
     Status,
+    /// Zone change notification (RFC 1996)
+    Notify,
+    /// Dynamic update (RFC 2136)
+    Update,
     /// Placeholder for values unknown to this library.
     Unknown {
         /// The unrecognized opcode.
@@ -34,6 +38,27 @@ pub enum Rcode {
     NotImplemented,
     /// The query was refused for policy reasons.
     Refused,
+    /// Some name that ought not to exist, does exist.
+    YXDomain,
+    /// Some RRset that ought not to exist, does exist.
+    YXRRSet,
+    /// Some RRset that ought to exist, does not exist.
+    NXRRSet,
+    /// The server is not authoritative for the zone named in the zone section.
+    NotAuth,
+    /// A name used in the prerequisite or update section is not within the zone.
+    NotZone,
+    /// (Extended) The TSIG signature failed to verify. Only carried in the error field of a TSIG
+    /// record: a header holding it is sent as `NotAuth`.
+    BadSig,
+    /// (Extended) The TSIG key is not recognized. Sent as `NotAuth` in the header.
+    BadKey,
+    /// (Extended) The TSIG signature is outside the allowed time window. Sent as `NotAuth` in the
+    /// header.
+    BadTime,
+    /// (Extended) The TSIG MAC was truncated below the allowed length. Sent as `NotAuth` in the
+    /// header.
+    BadTrunc,
     /// Placeholder for values unknown to this library.
     Unknown {
         /// The unrecognized response code.
@@ -109,8 +134,13 @@ impl Header {
 
     fn flags_to_u16(&self) -> u16 {
         let opcode: u8 = self.opcode.into();
-        let rcode: u8 = self.rcode.into();
-        let mut res = (rcode as u16) | ((opcode as u16) << 11);
+        // The TSIG errors do not fit in the header, which carries NOTAUTH alongside them (RFC 8945
+        // §5.3.2). Other extended response codes only keep their low 4 bits.
+        let rcode: u8 = match self.rcode {
+            Rcode::BadSig | Rcode::BadKey | Rcode::BadTime | Rcode::BadTrunc => Rcode::NotAuth,
+            rcode => rcode,
+        }.into();
+        let mut res = ((rcode & 0x0F) as u16) | ((opcode as u16) << 11);
         if self.qr {
             res |= 0b1000_0000_0000_0000;
         }
@@ -162,6 +192,8 @@ impl From<u8> for Opcode {
             0 => Opcode::Query,
             1 => Opcode::InverseQuery,
             2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
             x => Opcode::Unknown { value: x },
         }
     }
@@ -173,6 +205,8 @@ impl From<Opcode> for u8 {
             Opcode::Query => 0,
             Opcode::InverseQuery => 1,
             Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
             Opcode::Unknown { value: x } => x,
         }
     }
@@ -187,6 +221,15 @@ impl From<u8> for Rcode {
             3 => Rcode::NameError,
             4 => Rcode::NotImplemented,
             5 => Rcode::Refused,
+            6 => Rcode::YXDomain,
+            7 => Rcode::YXRRSet,
+            8 => Rcode::NXRRSet,
+            9 => Rcode::NotAuth,
+            10 => Rcode::NotZone,
+            16 => Rcode::BadSig,
+            17 => Rcode::BadKey,
+            18 => Rcode::BadTime,
+            22 => Rcode::BadTrunc,
             x => Rcode::Unknown { value: x },
         }
     }
@@ -201,6 +244,15 @@ impl From<Rcode> for u8 {
             Rcode::NameError => 3,
             Rcode::NotImplemented => 4,
             Rcode::Refused => 5,
+            Rcode::YXDomain => 6,
+            Rcode::YXRRSet => 7,
+            Rcode::NXRRSet => 8,
+            Rcode::NotAuth => 9,
+            Rcode::NotZone => 10,
+            Rcode::BadSig => 16,
+            Rcode::BadKey => 17,
+            Rcode::BadTime => 18,
+            Rcode::BadTrunc => 22,
             Rcode::Unknown { value: x } => x,
         }
     }
@@ -1,15 +1,31 @@
 //! A Rust library for DNS requests, answers, and resolving.
 
-mod resolve;
+pub mod resolve;
 pub mod message;
 pub mod rr;
+pub mod secondary;
+pub mod serial;
 pub mod names;
+pub mod notify;
+pub mod tsig;
+pub mod ttl;
+pub mod update;
+pub mod zone;
 mod header;
 mod question;
+mod random;
+mod tcp;
 
-pub use resolve::resolve;
+pub use resolve::{resolve, Lookup, ResolveError, Resolver, ResolverConfig};
+pub use header::{Opcode, Rcode};
 pub use message::Message;
+pub use names::Name;
+pub use question::{QType, Question};
 pub use rr::ResourceRecord;
+pub use serial::Serial;
+pub use ttl::Ttl;
+pub use update::{Update, UpdateError};
+pub use zone::Zone;
 
 #[cfg(test)]
 mod tests {
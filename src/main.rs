@@ -1,24 +1,46 @@
 use clap::Parser;
-use martin::resolve;
+use martin::resolve::{ResolvConf, HOSTS_PATH};
+use martin::{Resolver, ResolverConfig};
+use std::net::IpAddr;
 
 /// DNS resolver implementation
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     host: Option<String>,
+    /// Looks up the host names of an address
+    #[clap(short = 'x', long = "reverse", value_name = "ADDRESS", conflicts_with = "host")]
+    reverse: Option<IpAddr>,
 }
 
 fn main() {
     let args = Args::parse();
 
-    if let Some(host) = args.host {
+    // Short names are completed from the system's search list, and local overrides in the
+    // hosts file come before DNS
+    let mut config = ResolverConfig::default();
+    if let Ok(conf) = ResolvConf::system() {
+        config.search = conf.search;
+        config.ndots = conf.ndots;
+    }
+    let resolver = Resolver::new(config).with_hosts_file(HOSTS_PATH);
+
+    if let Some(addr) = args.reverse {
+        println!("Address: {addr}\n");
+        match resolver.reverse_lookup(addr) {
+            Ok(names) => match names.len() {
+                0 => println!("No pointer records"),
+                _ => {
+                    for name in names {
+                        println!("Name: {name}");
+                    }
+                }
+            },
+            Err(e) => eprintln!("Failed to query DNS: {e}"),
+        }
+    } else if let Some(host) = args.host {
         println!("Name: {host}\n");
-        let host = if host.ends_with(".") {
-            host
-        } else {
-            format!("{host}.")
-        };
-        match resolve(&host) {
+        match resolver.resolve(&host) {
             Ok(addresses) => match addresses.len() {
                 0 => println!("No address records"),
                 1 => println!("Address: {}", addresses[0]),
@@ -3,13 +3,14 @@ use crate::header::{Header, Opcode, Rcode};
 use crate::question::{QType, Question};
 use crate::rr::{Class, ResourceRecord, Type};
 use std::io::{Cursor, Write};
-use nom::bytes::complete::{tag, take_while_m_n};
+use nom::bytes::complete::{take, take_while_m_n};
 use nom::combinator::{eof, fail};
 use nom::IResult;
 use nom::multi::{count, length_data};
 use nom::number::complete::{be_u128, be_u16, be_u32, be_u8};
 use nom::sequence::tuple;
 use crate::names::{Name};
+use crate::ttl::Ttl;
 
 /// Describes a DNS query or response.
 #[derive(Debug, Clone, PartialEq)]
@@ -95,6 +96,8 @@ impl Display for Message {
                    Opcode::Query => "Q",
                    Opcode::InverseQuery => "I",
                    Opcode::Status => "S",
+                   Opcode::Notify => "N",
+                   Opcode::Update => "U",
                    Opcode::Unknown { .. } => " ",
                },
                if self.authoritative() { "A" } else { " " },
@@ -150,6 +153,20 @@ impl Message {
     }
 }
 
+/// Finds where the last resource record of the message in `buf` starts, which is where a
+/// `TSIG` record must be.
+pub(crate) fn last_record_offset(buf: &[u8]) -> Option<usize> {
+    let (mut i, header) = parse_header(buf).ok()?;
+    (i, _) = count(parse_question(buf), header.question_count as usize)(i).ok()?;
+    let records = header.answer_count as usize + header.ns_count as usize + header.additional_count as usize;
+    let mut last = None;
+    for _ in 0..records {
+        last = Some(buf.len() - i.len());
+        (i, _) = parse_rr(buf)(i).ok()?;
+    }
+    last
+}
+
 fn parse_message(buf: &[u8]) -> IResult<&[u8], Message> {
     let (i, header) = parse_header(buf)?;
     let (i, questions) = count(parse_question(buf), header.question_count as usize)(i)?;
@@ -198,18 +215,26 @@ fn parse_question<'a>(data: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8],
     }
 }
 
-fn parse_name<'a>(data: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Name> {
-    |i| -> IResult<&[u8], Name> {
+pub(crate) fn parse_name<'a>(data: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Name> {
+    move |i| parse_labels(data, data.len() - i.len())(i)
+}
+
+/// Parses the labels of a name which starts at offset `start` in `data`.
+///
+/// Compression pointers must point strictly before the start of the name holding them, so that
+/// following them always leads further back in the message and cannot loop.
+fn parse_labels<'a>(data: &'a [u8], start: usize) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Name> {
+    move |i| -> IResult<&[u8], Name> {
         let (i, length) = be_u8(i)?;
         if length == 0 {
             return Ok((i, Name { name: vec![0] }));
         }
         match length & 0xC0 {
             0 => {
-                let (i, first) = take_while_m_n(1, length as usize, |item: u8| item.is_ascii_alphanumeric())(i)?;
+                let (i, first) = take_while_m_n(1, length as usize, |item: u8| item.is_ascii_alphanumeric() || item == b'_')(i)?;
                 let rem = length as usize - first.len();
-                let (i, second) = take_while_m_n(rem, rem, |item: u8| item.is_ascii_alphanumeric() || item as char == '-')(i)?;
-                let (i, next) = parse_name(data)(i)?;
+                let (i, second) = take_while_m_n(rem, rem, |item: u8| item.is_ascii_alphanumeric() || item == b'-' || item == b'_')(i)?;
+                let (i, next) = parse_labels(data, start)(i)?;
                 let mut name = Vec::with_capacity(1 + length as usize + next.name.len());
                 name.push(length);
                 name.extend_from_slice(first);
@@ -221,10 +246,10 @@ fn parse_name<'a>(data: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], Name
                 let (i, offset_low) = be_u8(i)?;
                 let offset = (length as usize & 0x3F) << 8 | offset_low as usize;
                 // Refuse to look ahead in the data; compression is expected to only work in reverse
-                if offset > (data.len() - i.len()) {
+                if offset >= start {
                     fail(i)
                 } else {
-                    let (_, name) = parse_name(data)(&data[offset..])?;
+                    let (_, name) = parse_labels(data, offset)(&data[offset..])?;
                     Ok((i, name))
                 }
             }
@@ -270,22 +295,183 @@ fn parse_type(i: &[u8]) -> IResult<&[u8], Type> {
 // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 
 fn parse_rr<'a>(data: &'a [u8]) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], ResourceRecord> {
-    |i| -> IResult<&[u8], ResourceRecord> {
+    move |i| -> IResult<&[u8], ResourceRecord> {
         let (i, (name, rtype, class, ttl)) = tuple((parse_name(data), parse_type, parse_class, be_u32))(i)?;
-        match rtype {
+        let (rest, rdata) = length_data(be_u16)(i)?;
+        // The OPT pseudo-record reuses the class and TTL fields for EDNS parameters
+        if rtype == Type::OPT {
+            return Ok((rest, ResourceRecord::OPT {
+                payload_size: class.into(),
+                extended_rcode: (ttl >> 24) as u8,
+                version: (ttl >> 16) as u8,
+                dnssec_ok: ttl & 0x8000 != 0,
+                data: rdata.to_vec(),
+            }));
+        }
+        let ttl = Ttl::from_secs(ttl);
+        // Dynamic update prerequisites and deletions of class ANY or NONE may carry no RDATA,
+        // whatever their type. Anywhere else, the type decides whether empty RDATA is allowed.
+        if rdata.is_empty() && matches!(class, Class::Any | Class::None) {
+            return Ok((rest, ResourceRecord::Unknown { name, rtype, class, ttl, data: Vec::new() }));
+        }
+        // Names in the RDATA must be parsed from the message suffix so compression pointers resolve
+        let (i, _) = be_u16(i)?;
+        let (after, rr) = match rtype {
             Type::A => {
-                let (i, (_, addr)) = tuple((tag([0u8, 4u8]), be_u32))(i)?;
-                Ok((i, ResourceRecord::A { name, class, ttl: ttl as i32, addr: addr.into() }))
+                let (i, addr) = be_u32(i)?;
+                (i, ResourceRecord::A { name, class, ttl, addr: addr.into() })
             }
             Type::AAAA => {
-                let (i, (_, addr)) = tuple((tag([0u8, 16u8]), be_u128))(i)?;
-                Ok((i, ResourceRecord::AAAA { name, class, ttl: ttl as i32, addr: addr.into() }))
+                let (i, addr) = be_u128(i)?;
+                (i, ResourceRecord::AAAA { name, class, ttl, addr: addr.into() })
+            }
+            Type::NS => {
+                let (i, ns_name) = parse_name(data)(i)?;
+                (i, ResourceRecord::NS { name, class, ttl, ns_name })
+            }
+            Type::CNAME => {
+                let (i, cname) = parse_name(data)(i)?;
+                (i, ResourceRecord::CNAME { name, class, ttl, cname })
+            }
+            Type::DNAME => {
+                let (i, target) = parse_name(data)(i)?;
+                (i, ResourceRecord::DNAME { name, class, ttl, target })
             }
-            // TODO: Parse all known types
-            _ => {
-                let (i, data) = length_data(be_u16)(i)?;
-                Ok((i, ResourceRecord::Unknown { name, rtype, class, ttl: ttl as i32, data: data.into() }))
+            Type::PTR => {
+                let (i, ptrname) = parse_name(data)(i)?;
+                (i, ResourceRecord::PTR { name, class, ttl, ptrname })
             }
+            Type::MX => {
+                let (i, (preference, exchange)) = tuple((be_u16, parse_name(data)))(i)?;
+                (i, ResourceRecord::MX { name, class, ttl, preference, exchange })
+            }
+            Type::SRV => {
+                let (i, (priority, weight, port, target)) = tuple((be_u16, be_u16, be_u16, parse_name(data)))(i)?;
+                (i, ResourceRecord::SRV { name, class, ttl, priority, weight, port, target })
+            }
+            Type::SOA => {
+                let (i, (mname, rname, serial, refresh, retry, expire, minimum)) =
+                    tuple((parse_name(data), parse_name(data), be_u32, be_u32, be_u32, be_u32, be_u32))(i)?;
+                (i, ResourceRecord::SOA { name, class, ttl, mname, rname, serial: serial.into(), refresh, retry, expire, minimum })
+            }
+            Type::TXT => {
+                // Character strings hold arbitrary bytes, and must fill the RDATA exactly
+                let mut strings = Vec::new();
+                let mut rest = rdata;
+                while !rest.is_empty() {
+                    let (r, length) = be_u8(rest)?;
+                    let (r, string) = take(length)(r)?;
+                    strings.push(string.to_vec());
+                    rest = r;
+                }
+                // At least one character string is required
+                if strings.is_empty() {
+                    return fail(i);
+                }
+                (&i[rdata.len()..], ResourceRecord::TXT { name, class, ttl, data: strings })
+            }
+            Type::TSIG => {
+                let (i, (algorithm, time_high, time_low, fudge, mac, original_id, error, other)) =
+                    tuple((parse_name(data), be_u16, be_u32, be_u16, length_data(be_u16), be_u16, be_u16, length_data(be_u16)))(i)?;
+                let time_signed = (time_high as u64) << 32 | time_low as u64;
+                (i, ResourceRecord::TSIG { name, algorithm, time_signed, fudge, mac: mac.into(), original_id, error, other: other.into() })
+            }
+            _ => (&i[rdata.len()..], ResourceRecord::Unknown { name, rtype, class, ttl, data: rdata.into() }),
+        };
+        if i.len() - after.len() != rdata.len() {
+            return fail(i);
+        }
+        Ok((rest, rr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::QType;
+
+    fn with_answer(rr: &ResourceRecord) -> Message {
+        let mut msg = Message::query(1, false, Question::new("example.com.", QType::ByType(Type::TXT)).unwrap());
+        msg.header.answer_count = 1;
+        msg.answers.push(rr.clone());
+        msg
+    }
+
+    #[test]
+    fn txt_strings_may_hold_any_bytes() {
+        let txt = ResourceRecord::TXT {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(60),
+            data: vec![vec![0xff, 0x00, b'a'], Vec::new()],
+        };
+        let msg = with_answer(&txt);
+        assert_eq!(Message::decode(&msg.encode()).unwrap().answers, vec![txt]);
+
+        // The empty character string at the end claims a byte beyond the RDATA
+        let mut wire = msg.encode();
+        *wire.last_mut().unwrap() = 1;
+        assert!(Message::decode(&wire).is_err());
+    }
+
+    #[test]
+    fn long_txt_strings_are_split() {
+        let long = vec![b'x'; 300];
+        let msg = with_answer(&ResourceRecord::TXT {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(60),
+            data: vec![long],
+        });
+        match &Message::decode(&msg.encode()).unwrap().answers[0] {
+            ResourceRecord::TXT { data, .. } => assert_eq!(data, &[vec![b'x'; 255], vec![b'x'; 45]]),
+            rr => panic!("unexpected {rr}"),
+        }
+    }
+
+    #[test]
+    fn compression_pointers_must_lead_backwards() {
+        let mut wire = Message::query(1, false, Question::new("a.", QType::ByType(Type::A)).unwrap()).encode();
+        // The question name "a." at offset 12 is followed by a pointer back to its own start
+        wire.truncate(12);
+        wire.extend_from_slice(&[1, b'a', 0xC0, 12, 0, 1, 0, 1]);
+        assert!(Message::decode(&wire).is_err());
+        // A pointer to itself
+        wire.truncate(12);
+        wire.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]);
+        assert!(Message::decode(&wire).is_err());
+    }
+
+    #[test]
+    fn tsig_errors_are_sent_as_notauth_in_the_header() {
+        for rcode in [Rcode::BadSig, Rcode::BadKey, Rcode::BadTime, Rcode::BadTrunc] {
+            let query = Message::query(1, false, Question::new("a.", QType::ByType(Type::A)).unwrap());
+            let mut response = Message::response(query, false);
+            response.header.rcode = rcode;
+            assert_eq!(Message::decode(&response.encode()).unwrap().rcode(), Rcode::NotAuth);
+        }
+    }
+
+    #[test]
+    fn empty_rdata_is_only_accepted_for_updates() {
+        let a = ResourceRecord::A {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(60),
+            addr: [192, 0, 2, 1].into(),
+        };
+        // Cut the RDATA off, leaving RDLENGTH 0
+        let mut wire = with_answer(&a).encode();
+        wire.truncate(wire.len() - 6);
+        wire.extend_from_slice(&[0, 0]);
+        assert!(Message::decode(&wire).is_err());
+
+        // Deleting the RRset instead: class ANY, ahead of the TTL and RDLENGTH
+        let class = wire.len() - 8;
+        wire[class..class + 2].copy_from_slice(&[0, 255]);
+        match &Message::decode(&wire).unwrap().answers[0] {
+            ResourceRecord::Unknown { rtype: Type::A, class: Class::Any, data, .. } => assert!(data.is_empty()),
+            rr => panic!("unexpected {rr}"),
         }
     }
 }
@@ -2,6 +2,7 @@ use std::error;
 use std::fmt;
 use std::io;
 use std::io::{Cursor, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
 /// Representation of a domain name
@@ -48,6 +49,141 @@ impl Name {
         self.name == vec![0]
     }
 
+    /// Compares two names, ignoring the case of their labels.
+    pub fn eq_ignore_case(&self, other: &Name) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+    }
+
+    /// Returns a copy of this name with all labels converted to lower case.
+    pub fn to_lowercase(&self) -> Name {
+        Name { name: self.name.to_ascii_lowercase() }
+    }
+
+    /// Determines whether this name is equal to, or falls beneath, `zone`.
+    ///
+    /// ```
+    /// # use martin::Name;
+    /// let name: Name = "www.example.com.".parse().unwrap();
+    /// assert!(name.is_subdomain_of(&"example.com.".parse().unwrap()));
+    /// assert!(!name.is_subdomain_of(&"example.org.".parse().unwrap()));
+    /// ```
+    pub fn is_subdomain_of(&self, zone: &Name) -> bool {
+        let mut name = Some(self.clone());
+        while let Some(n) = name {
+            if n.eq_ignore_case(zone) {
+                return true;
+            }
+            name = n.parent();
+        }
+        false
+    }
+
+    /// Replaces the `suffix` ending this name with `replacement`, as a `DNAME` record does.
+    ///
+    /// Returns `None` if this name does not end in `suffix`, or the result would be too long.
+    ///
+    /// ```
+    /// # use martin::Name;
+    /// let name: Name = "www.example.com.".parse().unwrap();
+    /// let moved = name.replace_suffix(&"example.com.".parse().unwrap(), &"example.net.".parse().unwrap());
+    /// assert_eq!(moved, Some("www.example.net.".parse().unwrap()));
+    /// ```
+    pub fn replace_suffix(&self, suffix: &Name, replacement: &Name) -> Option<Name> {
+        if !self.is_subdomain_of(suffix) {
+            return None;
+        }
+        let prefix = &self.name[..self.name.len() - suffix.name.len()];
+        if prefix.len() + replacement.name.len() > 255 {
+            return None;
+        }
+        let mut name = prefix.to_vec();
+        name.extend_from_slice(&replacement.name);
+        Some(Name { name })
+    }
+
+    /// The name under `in-addr.arpa.` or `ip6.arpa.` which holds the `PTR` records for `addr`.
+    ///
+    /// ```
+    /// # use martin::Name;
+    /// let name = Name::reverse_from_ip("192.0.2.1".parse().unwrap());
+    /// assert_eq!(name.to_string(), "1.2.0.192.in-addr.arpa.");
+    /// assert!(Name::reverse_from_ip("2001:db8::1".parse().unwrap()).to_string()
+    ///     .starts_with("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"));
+    /// ```
+    pub fn reverse_from_ip(addr: IpAddr) -> Name {
+        let name = match addr {
+            IpAddr::V4(addr) => {
+                let [a, b, c, d] = addr.octets();
+                format!("{d}.{c}.{b}.{a}.in-addr.arpa.")
+            }
+            IpAddr::V6(addr) => {
+                let bits = u128::from(addr);
+                let mut name: String = (0..32).map(|i| format!("{:x}.", (bits >> (i * 4)) & 0xf)).collect();
+                name.push_str("ip6.arpa.");
+                name
+            }
+        };
+        name.parse().expect("reverse names are valid")
+    }
+
+    /// The network this name holds the reverse records of, as an address and a prefix length,
+    /// or `None` if it is not a name under `in-addr.arpa.` or `ip6.arpa.`.
+    ///
+    /// Names with fewer labels than a full address stand for the networks delegated at octet or
+    /// nibble boundaries. A first label such as `0-25` stands for a classless delegation within
+    /// an IPv4 /24, as described in RFC 2317.
+    ///
+    /// ```
+    /// # use martin::Name;
+    /// let name: Name = "1.2.0.192.in-addr.arpa.".parse().unwrap();
+    /// assert_eq!(name.to_reverse_ip(), Some(("192.0.2.1".parse().unwrap(), 32)));
+    /// let name: Name = "128-26.2.0.192.in-addr.arpa.".parse().unwrap();
+    /// assert_eq!(name.to_reverse_ip(), Some(("192.0.2.128".parse().unwrap(), 26)));
+    /// let name: Name = "8.b.d.0.1.0.0.2.ip6.arpa.".parse().unwrap();
+    /// assert_eq!(name.to_reverse_ip(), Some(("2001:db8::".parse().unwrap(), 32)));
+    /// ```
+    pub fn to_reverse_ip(&self) -> Option<(IpAddr, u8)> {
+        let name = self.to_lowercase().to_string();
+        if let Some(labels) = reverse_labels(&name, "in-addr.arpa.") {
+            let mut octets = [0u8; 4];
+            let mut prefix = 0;
+            for (i, label) in labels.iter().rev().enumerate() {
+                if i == octets.len() {
+                    return None;
+                }
+                match label.split_once('-') {
+                    Some((start, len)) if i == 3 && i + 1 == labels.len() => {
+                        let (start, len): (u8, u8) = (start.parse().ok()?, len.parse().ok()?);
+                        if !(25..=32).contains(&len) || start & !(0xffu8 << (32 - len)) != 0 {
+                            return None;
+                        }
+                        octets[i] = start;
+                        prefix = len;
+                    }
+                    _ => {
+                        octets[i] = label.parse().ok()?;
+                        prefix += 8;
+                    }
+                }
+            }
+            return Some((IpAddr::V4(Ipv4Addr::from(octets)), prefix));
+        }
+        let labels = reverse_labels(&name, "ip6.arpa.")?;
+        if labels.len() > 32 {
+            return None;
+        }
+        let mut bits: u128 = 0;
+        for label in labels.iter().rev() {
+            if label.len() != 1 {
+                return None;
+            }
+            bits = bits << 4 | u128::from_str_radix(label, 16).ok()?;
+        }
+        let prefix = labels.len() as u32 * 4;
+        let addr = bits.checked_shl(128 - prefix).unwrap_or(0);
+        Some((IpAddr::V6(Ipv6Addr::from(addr)), prefix as u8))
+    }
+
     pub fn write_to<T>(&self, cursor: &mut Cursor<T>) -> io::Result<()> where Cursor<T>: Write {
         // TODO: Add name compression
         cursor.write_all(&self.name)
@@ -61,7 +197,7 @@ pub enum NameParseError {
     TotalLengthGreaterThan255(usize),
     /// Label length cannot exceed 63
     LabelLengthGreaterThan63(usize),
-    /// Valid characters are 'a-z', 'A-z', '0-9', and '-'
+    /// Valid characters are 'a-z', 'A-z', '0-9', '-' and '_'
     InvalidCharacter(char),
     /// '-' cannot be the first character in a label
     HypenFirstCharacterInLabel,
@@ -83,7 +219,7 @@ impl fmt::Display for NameParseError {
             }
             InvalidCharacter(x) => {
                 write!(fmt,
-                       "Valid characters are a-z, A-Z, and '-'. Found: '\\x{:x}'",
+                       "Valid characters are a-z, A-Z, 0-9, '-' and '_'. Found: '\\x{:x}'",
                        x as u32)
             }
             HypenFirstCharacterInLabel => {
@@ -104,7 +240,7 @@ impl error::Error for NameParseError {
         match *self {
             TotalLengthGreaterThan255(_) => "Name length must be less than 255",
             LabelLengthGreaterThan63(_) => "Label length must be less than 63",
-            InvalidCharacter(_) => "Valid characters are a-z, A-Z, and '-'.",
+            InvalidCharacter(_) => "Valid characters are a-z, A-Z, 0-9, '-' and '_'.",
             HypenFirstCharacterInLabel => "Hyphen ('-') cannot be the first character in a label",
             NameMustEndInRootLabel => "Names must end in the root label ('.')",
             EmptyNonRootLabel => "The root label is only allowed at the end of names",
@@ -138,7 +274,7 @@ impl FromStr for Name {
                     label_len = 0;
                 }
                 '-' if label_len == 0 => return Err(HypenFirstCharacterInLabel),
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => {
                     label_len += 1;
                     name.push(c as u8);
                 }
@@ -172,6 +308,96 @@ impl fmt::Display for Name {
     }
 }
 
+/// The labels of `name` before `suffix`, or `None` if it does not end in `suffix`.
+fn reverse_labels<'a>(name: &'a str, suffix: &str) -> Option<Vec<&'a str>> {
+    match name.strip_suffix(suffix)? {
+        "" => Some(Vec::new()),
+        labels => Some(labels.strip_suffix('.')?.split('.').collect()),
+    }
+}
+
+/// A domain name as a user might type it, which is relative unless it ends with a '.'
+///
+/// Relative names are made absolute by appending a domain to them, such as each of a
+/// resolver's search domains in turn.
+///
+/// ```
+/// # use martin::names::RelativeName;
+/// let host: RelativeName = "www".parse().unwrap();
+/// assert!(!host.is_absolute());
+/// assert_eq!(host.append(&"example.com.".parse().unwrap()), Ok("www.example.com.".parse().unwrap()));
+///
+/// let host: RelativeName = "www.example.com.".parse().unwrap();
+/// assert!(host.is_absolute());
+/// assert_eq!(host.ndots(), 2);
+/// ```
+#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+pub struct RelativeName {
+    /// The labels of the name, followed by the root label whether or not it was written
+    labels: Name,
+    absolute: bool,
+}
+
+impl RelativeName {
+    /// Whether the name was written with its trailing '.', and so is never searched for.
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    /// The number of dots between the labels of the name, not counting any trailing dot.
+    pub fn ndots(&self) -> usize {
+        let mut count: usize = 0;
+        let mut pos = 0;
+        while self.labels.name[pos] != 0 {
+            count += 1;
+            pos += self.labels.name[pos] as usize + 1;
+        }
+        count.saturating_sub(1)
+    }
+
+    /// The name taken as it is, relative to the root.
+    pub fn to_absolute(&self) -> Name {
+        self.labels.clone()
+    }
+
+    /// The name relative to `origin`, unless it is already absolute.
+    pub fn append(&self, origin: &Name) -> Result<Name, NameParseError> {
+        if self.absolute {
+            return Ok(self.labels.clone());
+        }
+        let mut name = self.labels.name[..self.labels.name.len() - 1].to_vec();
+        name.extend_from_slice(&origin.name);
+        if name.len() > 255 {
+            return Err(NameParseError::TotalLengthGreaterThan255(name.len()));
+        }
+        Ok(Name { name })
+    }
+}
+
+impl FromStr for RelativeName {
+    type Err = NameParseError;
+    fn from_str(s: &str) -> Result<RelativeName, NameParseError> {
+        if s.ends_with('.') {
+            return Ok(RelativeName { labels: s.parse()?, absolute: true });
+        }
+        if s.is_empty() {
+            return Err(NameParseError::EmptyNonRootLabel);
+        }
+        Ok(RelativeName { labels: format!("{s}.").parse()?, absolute: false })
+    }
+}
+
+impl fmt::Display for RelativeName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.labels.to_string();
+        if self.absolute {
+            write!(fmt, "{}", name)
+        } else {
+            write!(fmt, "{}", name.trim_end_matches('.'))
+        }
+    }
+}
+
 // /// Parses a byte stream into a `Name`
 // pub fn parse_name<'a>(i: &'a [u8], data: &'a [u8]) -> IResult<&'a [u8], Name, ParseError> {
 //     map!(i,
@@ -244,7 +470,6 @@ impl fmt::Display for Name {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use nom::IResult::Done;
 
     #[test]
     fn parse_str_root_label() {
@@ -291,21 +516,22 @@ mod tests {
         // 40: FOO.F.ISI.ARPA.
         // 46: <root>
         let a = b"12345678901234567890\x01F\x03ISI\x04ARPA\x0012345678\x03FOO\xC0\x14\x00abcd";
+        let parse_name = crate::message::parse_name(&a[..]);
 
-        assert_eq!(parse_name(&a[20..], &a[..]),
-                   Done(&a[32..],
-                        Name { name: b"\x01F\x03ISI\x04ARPA\x00".to_vec() }));
-        assert_eq!(parse_name(&a[22..], &a[..]),
-                   Done(&a[32..], Name { name: b"\x03ISI\x04ARPA\x00".to_vec() }));
-        assert_eq!(parse_name(&a[40..], &a[..]),
-                   Done(&a[46..],
-                        Name { name: b"\x03FOO\x01F\x03ISI\x04ARPA\x00".to_vec() }));
+        assert_eq!(parse_name(&a[20..]),
+                   Ok((&a[32..],
+                       Name { name: b"\x01F\x03ISI\x04ARPA\x00".to_vec() })));
+        assert_eq!(parse_name(&a[22..]),
+                   Ok((&a[32..], Name { name: b"\x03ISI\x04ARPA\x00".to_vec() })));
+        assert_eq!(parse_name(&a[40..]),
+                   Ok((&a[46..],
+                       Name { name: b"\x03FOO\x01F\x03ISI\x04ARPA\x00".to_vec() })));
         // This one is fun: make sure that extra names aren't swallowed or parsed:
-        assert_eq!(parse_name(&a[44..], &a[..]),
-                   Done(&b"\x00abcd"[..],
-                        Name { name: b"\x01F\x03ISI\x04ARPA\x00".to_vec() }));
-        assert_eq!(parse_name(&a[46..], &a[..]),
-                   Done(&b"abcd"[..], Name { name: b"\x00".to_vec() }));
+        assert_eq!(parse_name(&a[44..]),
+                   Ok((&b"\x00abcd"[..],
+                       Name { name: b"\x01F\x03ISI\x04ARPA\x00".to_vec() })));
+        assert_eq!(parse_name(&a[46..]),
+                   Ok((&b"abcd"[..], Name { name: b"\x00".to_vec() })));
     }
 
     #[test]
@@ -342,4 +568,32 @@ mod tests {
         let name = "test..";
         assert_eq!(name.parse::<Name>(), Err(EmptyNonRootLabel));
     }
+
+    #[test]
+    fn reverse_names_round_trip() {
+        for addr in ["192.0.2.1", "0.0.0.0", "2001:db8::1", "::ffff:192.0.2.1"] {
+            let addr: IpAddr = addr.parse().unwrap();
+            let prefix = if addr.is_ipv4() { 32 } else { 128 };
+            assert_eq!(Name::reverse_from_ip(addr).to_reverse_ip(), Some((addr, prefix)));
+        }
+        let reverse = |name: &str| name.parse::<Name>().unwrap().to_reverse_ip();
+        assert_eq!(reverse("2.0.192.IN-ADDR.ARPA."), Some(("192.0.2.0".parse().unwrap(), 24)));
+        assert_eq!(reverse("in-addr.arpa."), Some(("0.0.0.0".parse().unwrap(), 0)));
+        assert_eq!(reverse("0-25.2.0.192.in-addr.arpa."), Some(("192.0.2.0".parse().unwrap(), 25)));
+        assert_eq!(reverse("1-25.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("0-24.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("0-33.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("0-25.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("x-25.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("1.1.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("1.1.1.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("256.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("x.2.0.192.in-addr.arpa."), None);
+        assert_eq!(reverse("ip6.arpa."), Some(("::".parse().unwrap(), 0)));
+        assert_eq!(reverse("10.8.b.d.0.1.0.0.2.ip6.arpa."), None);
+        assert_eq!(reverse("g.8.b.d.0.1.0.0.2.ip6.arpa."), None);
+        assert_eq!(reverse("ab.b.d.0.1.0.0.2.ip6.arpa."), None);
+        assert_eq!(reverse(&format!("{}ip6.arpa.", "0.".repeat(33))), None);
+        assert_eq!(reverse("www.example.com."), None);
+    }
 }
@@ -0,0 +1,310 @@
+//! Prompt notification of zone changes from primary to secondary servers (RFC 1996).
+
+use crate::header::{Header, Opcode, Rcode};
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::{QType, Question};
+use crate::random::random_u16;
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::serial::Serial;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Controls how notifications are retransmitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyConfig {
+    /// How long to wait for the first acknowledgement. The wait doubles for each retry.
+    pub timeout: Duration,
+    /// How many times to retransmit an unacknowledged notification.
+    pub retries: usize,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> NotifyConfig {
+        NotifyConfig {
+            timeout: Duration::from_secs(2),
+            retries: 4,
+        }
+    }
+}
+
+/// Errors that may occur while sending a notification or checking for a refresh.
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("no acknowledgement after {0} attempts")]
+    NoResponse(usize),
+
+    #[error("the server responded with {0:?}")]
+    Rejected(Rcode),
+
+    #[error("the primary did not return an SOA record for the zone")]
+    MissingSoa,
+
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+
+    #[error("deserialization error: {0}")]
+    DeseralizationFailed(#[from] nom::Err<nom::error::Error<Vec<u8>>>),
+}
+
+/// Receives the notifications accepted by `handle_notify`.
+///
+/// Closures taking the same arguments implement this trait.
+pub trait NotifyHandler {
+    /// Called when `from` announces that `zone` has changed, with the new serial if one was sent.
+    ///
+    /// Returning an error refuses the notification with that response code.
+    fn notify(&mut self, zone: &Name, serial: Option<Serial>, from: SocketAddr) -> Result<(), Rcode>;
+}
+
+impl<F> NotifyHandler for F where F: FnMut(&Name, Option<Serial>, SocketAddr) -> Result<(), Rcode> {
+    fn notify(&mut self, zone: &Name, serial: Option<Serial>, from: SocketAddr) -> Result<(), Rcode> {
+        self(zone, serial, from)
+    }
+}
+
+/// Creates the `NOTIFY` message announcing the zone whose new `SOA` record is `soa`.
+pub fn notify_message(id: u16, soa: &ResourceRecord) -> Message {
+    let mut header = Header::query(id, Opcode::Notify, false, 1);
+    header.authoritative = true;
+    header.answer_count = 1;
+    Message {
+        header,
+        questions: vec![Question {
+            qname: soa.name().expect("SOA records have a name").clone(),
+            qtype: QType::ByType(Type::SOA),
+            qclass: soa.class().expect("SOA records have a class"),
+        }],
+        answers: vec![soa.clone()],
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+/// Notifies each secondary that the zone whose new `SOA` record is `soa` has changed.
+///
+/// Returns the outcome for each secondary, in the order given.
+pub fn send_notify(soa: &ResourceRecord,
+                   secondaries: &[SocketAddr],
+                   config: &NotifyConfig)
+                   -> Vec<(SocketAddr, Result<(), NotifyError>)> {
+    secondaries.iter()
+        .map(|addr| (*addr, notify_one(soa, *addr, config)))
+        .collect()
+}
+
+fn notify_one(soa: &ResourceRecord, addr: SocketAddr, config: &NotifyConfig) -> Result<(), NotifyError> {
+    let id = random_u16();
+    let msg = notify_message(id, soa);
+    let response = exchange(&msg, addr, config.timeout, config.retries)?;
+    match response.rcode() {
+        Rcode::NoError => Ok(()),
+        rcode => Err(NotifyError::Rejected(rcode)),
+    }
+}
+
+/// Builds the acknowledgement for a received `NOTIFY`, passing it on to `handler`.
+pub fn handle_notify<H: NotifyHandler>(request: &Message, from: SocketAddr, handler: &mut H) -> Message {
+    let mut response = Message::response(request.clone(), false);
+    response.header.answer_count = 0;
+    response.header.authoritative = true;
+    response.header.rcode = match request.questions.as_slice() {
+        _ if request.opcode() != Opcode::Notify => Rcode::FormatError,
+        [q] if q.qtype == QType::ByType(Type::SOA) => {
+            let serial = request.answers.iter()
+                .find_map(|rr| match rr {
+                    ResourceRecord::SOA { name, serial, .. } if name.eq_ignore_case(&q.qname) => Some(*serial),
+                    _ => None,
+                });
+            match handler.notify(&q.qname, serial, from) {
+                Ok(()) => Rcode::NoError,
+                Err(rcode) => rcode,
+            }
+        }
+        [_] => Rcode::NotImplemented,
+        _ => Rcode::FormatError,
+    };
+    response
+}
+
+/// Asks `primary` for the serial of `zone`, in `class`, returning it if it is newer than
+/// `current`.
+pub fn check_refresh(primary: SocketAddr,
+                     zone: &Name,
+                     class: Class,
+                     current: Serial,
+                     config: &NotifyConfig)
+                     -> Result<Option<Serial>, NotifyError> {
+    let serial = query_serial(primary, zone, class, config.timeout, config.retries)?;
+    Ok(if serial > current { Some(serial) } else { None })
+}
+
+/// Asks `primary` for the current serial of `zone`, in `class`.
+pub(crate) fn query_serial(primary: SocketAddr,
+                           zone: &Name,
+                           class: Class,
+                           timeout: Duration,
+                           retries: usize)
+                           -> Result<Serial, NotifyError> {
+    let id = random_u16();
+    let question = Question {
+        qname: zone.clone(),
+        qtype: QType::ByType(Type::SOA),
+        qclass: class,
+    };
+    let response = exchange(&Message::query(id, false, question), primary, timeout, retries)?;
+    if response.rcode() != Rcode::NoError {
+        return Err(NotifyError::Rejected(response.rcode()));
+    }
+    response.answers.iter()
+        .find_map(|rr| match rr {
+            ResourceRecord::SOA { name, serial, .. } if name.eq_ignore_case(zone) => Some(*serial),
+            _ => None,
+        })
+        .ok_or(NotifyError::MissingSoa)
+}
+
+/// Sends `msg` to `addr` over UDP, retransmitting until a matching response arrives.
+fn exchange(msg: &Message, addr: SocketAddr, timeout: Duration, retries: usize) -> Result<Message, NotifyError> {
+    let socket = match addr {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.connect(addr)?;
+    let data = msg.encode();
+    let mut timeout = timeout;
+    let mut buf = vec![0u8; 65_535];
+    for _ in 0..=retries {
+        socket.send(&data)?;
+        // Datagrams which are not the response must not extend the wait
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let size = match socket.recv(&mut buf) {
+                Ok(size) => size,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e.into()),
+            };
+            let response = match Message::decode(&buf[..size]) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            if response.is_response() && response.id() == msg.id() && response.opcode() == msg.opcode() {
+                return Ok(response);
+            }
+        }
+        timeout *= 2;
+    }
+    Err(NotifyError::NoResponse(retries + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ttl::Ttl;
+    use std::thread;
+
+    fn soa(serial: u32) -> ResourceRecord {
+        ResourceRecord::SOA {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(3600),
+            mname: "ns1.example.com.".parse().unwrap(),
+            rname: "hostmaster.example.com.".parse().unwrap(),
+            serial: Serial(serial),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        }
+    }
+
+    fn config() -> NotifyConfig {
+        NotifyConfig { timeout: Duration::from_millis(100), retries: 2 }
+    }
+
+    #[test]
+    fn notify_is_acknowledged_after_retry() {
+        let secondary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = secondary.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            // Drop the first transmission to force a retry
+            secondary.recv_from(&mut buf).unwrap();
+            let (size, from) = secondary.recv_from(&mut buf).unwrap();
+            let request = Message::decode(&buf[..size]).unwrap();
+            let mut notified = None;
+            let response = handle_notify(&request, from, &mut |zone: &Name, serial: Option<Serial>, _| {
+                notified = Some((zone.clone(), serial));
+                Ok(())
+            });
+            secondary.send_to(&response.encode(), from).unwrap();
+            notified
+        });
+
+        let results = send_notify(&soa(5), &[addr], &config());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+        assert_eq!(server.join().unwrap(), Some(("example.com.".parse().unwrap(), Some(Serial(5)))));
+    }
+
+    #[test]
+    fn refused_notify_is_reported() {
+        let request = notify_message(9, &soa(5));
+        let response = handle_notify(&request, "127.0.0.1:53".parse().unwrap(),
+                                     &mut |_: &Name, _, _| Err(Rcode::NotAuth));
+        assert_eq!(response.rcode(), Rcode::NotAuth);
+        assert_eq!(response.id(), 9);
+        assert!(response.is_response());
+    }
+
+    #[test]
+    fn refresh_check_compares_primary_serial() {
+        let primary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = primary.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for _ in 0..2 {
+                let (size, from) = primary.recv_from(&mut buf).unwrap();
+                let mut response = Message::response(Message::decode(&buf[..size]).unwrap(), false);
+                response.answers.push(soa(7));
+                response.header.answer_count = 1;
+                primary.send_to(&response.encode(), from).unwrap();
+            }
+        });
+
+        let zone = "example.com.".parse().unwrap();
+        assert_eq!(check_refresh(addr, &zone, Class::Internet, Serial(5), &config()).unwrap(), Some(Serial(7)));
+        assert_eq!(check_refresh(addr, &zone, Class::Internet, Serial(7), &config()).unwrap(), None);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn junk_does_not_extend_the_wait() {
+        let primary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = primary.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (size, from) = primary.recv_from(&mut buf).unwrap();
+            // Keep sending datagrams which are not the response, and never answer
+            for _ in 0..50 {
+                primary.send_to(b"junk", from).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+            Message::decode(&buf[..size]).unwrap().questions[0].qclass
+        });
+
+        let start = Instant::now();
+        let config = NotifyConfig { timeout: Duration::from_millis(100), retries: 0 };
+        let result = check_refresh(addr, &"example.com.".parse().unwrap(), Class::Chaos, Serial(5), &config);
+        assert!(matches!(result, Err(NotifyError::NoResponse(1))));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(server.join().unwrap(), Class::Chaos);
+    }
+}
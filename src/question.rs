@@ -12,6 +12,8 @@ pub enum QType {
     ByType(Type),
     /// A query requesting all records for a name.
     Any,
+    /// A request for a transfer of an entire zone.
+    Axfr,
 }
 
 /// Describes a DNS query.
@@ -45,6 +47,7 @@ impl Question {
 impl From<u16> for QType {
     fn from(value: u16) -> QType {
         match Type::from(value) {
+            Type::Unknown { value: 252 } => QType::Axfr,
             Type::Unknown { value: 255 } => QType::Any,
             t => QType::ByType(t),
         }
@@ -54,6 +57,7 @@ impl From<u16> for QType {
 impl From<QType> for u16 {
     fn from(value: QType) -> u16 {
         match value {
+            QType::Axfr => 252,
             QType::Any => 255,
             QType::ByType(t) => t.into(),
         }
@@ -0,0 +1,13 @@
+//! Unpredictable values, taken from the operating system's random number generator.
+
+/// Fills `buf` with random bytes.
+pub(crate) fn fill(buf: &mut [u8]) {
+    getrandom::getrandom(buf).expect("the operating system's random number generator failed");
+}
+
+/// A random 16 bit value, for message IDs and source ports.
+pub(crate) fn random_u16() -> u16 {
+    let mut buf = [0u8; 2];
+    fill(&mut buf);
+    u16::from_be_bytes(buf)
+}
@@ -0,0 +1,198 @@
+use super::network::{answers, Network, MAX_UDP_SIZE};
+use super::transport::bind_random_port;
+use super::{Lookup, ResolveError, Resolver, ResolverConfig};
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::QType;
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::tcp::write_tcp;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// A resolver for tokio applications, which waits for servers without blocking its thread.
+///
+/// It follows the same steps as the `Resolver` it is made from, and shares its cache, but
+/// looks up the IPv4 and IPv6 addresses of a host concurrently.
+///
+/// Lookups may be dropped at any point, for instance when they lose a `tokio::select!`. Every
+/// wait is bounded by the configured timeouts, and nothing is left half done.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), martin::ResolveError> {
+/// use martin::resolve::AsyncResolver;
+/// let addrs = AsyncResolver::default().resolve("www.example.com.").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AsyncResolver {
+    resolver: Resolver,
+}
+
+impl From<Resolver> for AsyncResolver {
+    fn from(resolver: Resolver) -> AsyncResolver {
+        AsyncResolver { resolver }
+    }
+}
+
+impl AsyncResolver {
+    /// Creates an `AsyncResolver` with the given configuration.
+    pub fn new(config: ResolverConfig) -> AsyncResolver {
+        AsyncResolver::from(Resolver::new(config))
+    }
+
+    /// Creates a stub resolver which uses the recursive resolvers in `/etc/resolv.conf`, after
+    /// the hosts listed in `/etc/hosts`.
+    pub fn system() -> std::io::Result<AsyncResolver> {
+        Ok(AsyncResolver::from(Resolver::system()?))
+    }
+
+    /// The blocking resolver sharing this one's configuration and cache.
+    pub fn blocking(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// Resolves `host` to its IPv4 and IPv6 addresses. See `Resolver::resolve`.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        self.check_hosts();
+        let addrs = self.resolver.resolve_on(&Tokio, host).await;
+        self.prefetch();
+        addrs
+    }
+
+    /// Looks up the records of type `qtype` and class `class` for `name`. See
+    /// `Resolver::lookup`.
+    pub async fn lookup(&self, name: &str, qtype: QType, class: Class) -> Result<Lookup, ResolveError> {
+        self.check_hosts();
+        let lookup = self.resolver.lookup_on(&Tokio, name, qtype, class).await;
+        self.prefetch();
+        lookup
+    }
+
+    /// Looks up the host names of `addr`, from the `PTR` records under `in-addr.arpa.` or
+    /// `ip6.arpa.`.
+    pub async fn reverse_lookup(&self, addr: IpAddr) -> Result<Vec<Name>, ResolveError> {
+        let name = Name::reverse_from_ip(addr).to_string();
+        Ok(self.lookup(&name, QType::ByType(Type::PTR), Class::Internet).await?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::PTR { ptrname, .. } => Some(ptrname.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Asks the root hints for the current root servers. See `Resolver::prime`.
+    pub async fn prime(&self) -> Result<(), ResolveError> {
+        self.resolver.prime_before(&Tokio, Instant::now() + self.resolver.config.deadline).await
+    }
+
+    /// Checks the hosts file for changes on a blocking thread, if it is due to be checked. Lookups
+    /// carry on with the hosts as they were until it has been read.
+    fn check_hosts(&self) {
+        if let Some(hosts) = &self.resolver.hosts {
+            if hosts.due(Instant::now()) {
+                let hosts = hosts.clone();
+                tokio::task::spawn_blocking(move || hosts.reload());
+            }
+        }
+    }
+
+    /// Refreshes the cache entries which were used shortly before expiring, each in its own
+    /// task. Only a few are refreshed at once.
+    fn prefetch(&self) {
+        for question in self.resolver.due_for_prefetch() {
+            let resolver = self.resolver.clone();
+            tokio::spawn(async move { resolver.refresh(&Tokio, question).await });
+        }
+    }
+}
+
+/// The network of the `AsyncResolver`, which uses tokio's sockets and timers.
+#[derive(Debug, Clone, Copy)]
+struct Tokio;
+
+impl Network for Tokio {
+    async fn udp(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        let socket = bind_random_port(server)?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        // The connected socket only receives datagrams from `server`
+        socket.connect(server).await?;
+        socket.send(&msg.encode()).await?;
+        let mut buf = vec![0u8; MAX_UDP_SIZE];
+        let receive = async {
+            loop {
+                let size = socket.recv(&mut buf).await?;
+                match Message::decode(&buf[..size]) {
+                    Ok(reply) if answers(&reply, msg) => return Ok(reply),
+                    _ => log::debug!("discarding a response from {server} which does not match the query"),
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, receive).await {
+            Ok(result) => result.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn tcp(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        let mut query = Vec::new();
+        write_tcp(&mut query, msg)?;
+        let exchange = async {
+            let mut stream = TcpStream::connect(server).await?;
+            stream.write_all(&query).await?;
+            loop {
+                let mut data = vec![0u8; stream.read_u16().await? as usize];
+                stream.read_exact(&mut data).await?;
+                let reply = Message::decode(&data)?;
+                if answers(&reply, msg) {
+                    return Ok(reply);
+                }
+                log::debug!("discarding a response from {server} which does not match the query");
+            }
+        };
+        match tokio::time::timeout(timeout, exchange).await {
+            Ok(result) => result.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::tests::hierarchy;
+    use std::future::Future;
+    use std::net::Ipv4Addr;
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn resolves_through_local_hierarchy() {
+        let resolver = AsyncResolver::new(hierarchy(0));
+        run(async {
+            assert_eq!(resolver.resolve("www.example.com.").await.unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+            assert!(matches!(resolver.resolve("missing.example.com.").await, Err(ResolveError::NoSuchDomain)));
+            let lookup = resolver.lookup("alias.example.com.", QType::ByType(Type::A), Class::Internet).await.unwrap();
+            assert_eq!(lookup.canonical_name, "www.example.com.".parse().unwrap());
+        });
+        // The blocking resolver shares the cache
+        assert_eq!(resolver.blocking().resolve("www.example.com.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert!(resolver.blocking().cache_stats().hits > 0);
+    }
+
+    #[test]
+    fn lookups_can_be_abandoned() {
+        // The first two queries to the root are lost, so the lookup waits to retransmit them
+        let resolver = AsyncResolver::new(hierarchy(2));
+        run(async {
+            let abandoned = tokio::time::timeout(Duration::from_millis(20), resolver.resolve("www.example.com.")).await;
+            assert!(abandoned.is_err());
+            assert_eq!(resolver.resolve("www.example.com.").await.unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        });
+    }
+}
@@ -0,0 +1,514 @@
+use crate::names::Name;
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::ttl::Ttl;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use std::mem::{size_of, size_of_val};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The most entries refreshed in the background at once. Entries due beyond this are left to
+/// expire.
+const MAX_REFRESHING: usize = 8;
+
+/// Counters describing how well a `Resolver`'s cache is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Lookups answered from the cache.
+    pub hits: u64,
+    /// Lookups which had to query servers.
+    pub misses: u64,
+    /// Entries removed to stay within the memory limit.
+    pub evictions: u64,
+    /// Lookups answered with expired entries, because the servers could not be reached.
+    pub stale_hits: u64,
+    /// Entries refreshed in the background before they expired.
+    pub prefetches: u64,
+    /// Entries currently held, including any which have expired but not yet been removed.
+    pub entries: usize,
+    /// Roughly how many bytes those entries take up.
+    pub bytes: usize,
+}
+
+/// What the cache knows about a name, type and class.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cached {
+    /// The records of the type, with their TTLs reduced to the time left before they expire.
+    Records(Vec<ResourceRecord>),
+    /// The name has no records of the type. Holds the `SOA` record the denial came with.
+    NoData(ResourceRecord),
+    /// The name does not exist at all. Holds the `SOA` record the denial came with.
+    NoSuchDomain(ResourceRecord),
+}
+
+impl Cached {
+    /// Gives every record `ttl` as its TTL.
+    fn with_ttl(&self, ttl: Ttl) -> Cached {
+        match self {
+            Cached::Records(records) => Cached::Records(records.iter().map(|rr| with_ttl(rr, ttl)).collect()),
+            Cached::NoData(soa) => Cached::NoData(with_ttl(soa, ttl)),
+            Cached::NoSuchDomain(soa) => Cached::NoSuchDomain(with_ttl(soa, ttl)),
+        }
+    }
+}
+
+/// How far cached data can be trusted, lowest first, after RFC 2181 §5.4.1. Data is never
+/// replaced by less trustworthy data while it is still fresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Trust {
+    /// Addresses from the additional section, such as glue.
+    Additional,
+    /// Servers from the authority section of a referral, which the parent zone is not
+    /// authoritative for.
+    Referral,
+    /// Answers and denials from servers authoritative for them.
+    Answer,
+}
+
+/// Identifies an entry. `rtype` is `None` for the denial of a whole name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    name: Name,
+    rtype: Option<Type>,
+    class: Class,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Cached,
+    /// How long the entry was cached for.
+    ttl: Duration,
+    expires: Instant,
+    /// Whether the entry has already been handed out for prefetching.
+    refreshing: bool,
+    /// The position of this entry in `Inner::recency`.
+    used: u64,
+    /// Roughly how many bytes the entry takes up.
+    size: usize,
+    trust: Trust,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// Keys by when they were last used, oldest first.
+    recency: BTreeMap<u64, Key>,
+    clock: u64,
+    /// The total size of the entries.
+    bytes: usize,
+    stats: CacheStats,
+    /// How long after expiring entries are kept, to be served if the servers cannot be reached.
+    max_stale: Duration,
+    /// The TTL given to the records of expired entries.
+    stale_ttl: Ttl,
+    /// Whether to hand out popular entries for refreshing just before they expire.
+    prefetch: bool,
+    /// Entries waiting to be refreshed.
+    due: Vec<(Name, Type, Class)>,
+    /// Entries being refreshed.
+    refreshing: HashSet<(Name, Type, Class)>,
+}
+
+/// A thread-safe cache of RRsets and denials, which honours TTLs and evicts the least recently
+/// used entries once full.
+#[derive(Debug)]
+pub(crate) struct Cache {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+    max_ttl: Ttl,
+    max_negative_ttl: Ttl,
+}
+
+impl Cache {
+    /// Creates a cache whose entries take up roughly `max_bytes` at most. TTLs are capped at
+    /// `max_ttl`, and at `max_negative_ttl` for denials.
+    pub fn new(max_bytes: usize, max_ttl: Ttl, max_negative_ttl: Ttl) -> Cache {
+        Cache { inner: Mutex::default(), max_bytes, max_ttl, max_negative_ttl }
+    }
+
+    /// Keeps entries for `max_stale` after they expire, so they can be served with a TTL of
+    /// `stale_ttl` when the servers cannot be reached (RFC 8767).
+    pub fn with_serve_stale(mut self, max_stale: Duration, stale_ttl: Ttl) -> Cache {
+        let inner = self.inner.get_mut().unwrap();
+        inner.max_stale = max_stale;
+        inner.stale_ttl = stale_ttl;
+        self
+    }
+
+    /// Marks entries for refreshing when they are used in the last tenth of their TTL.
+    pub fn with_prefetch(mut self, prefetch: bool) -> Cache {
+        self.inner.get_mut().unwrap().prefetch = prefetch;
+        self
+    }
+
+    /// Looks up each of `rtypes` at `name` in turn, counting a single hit or miss.
+    ///
+    /// A denial of the whole name answers every type.
+    pub fn get(&self, name: &Name, rtypes: &[Type], class: Class, now: Instant) -> Option<Cached> {
+        let mut inner = self.inner.lock().unwrap();
+        let found = rtypes.iter().map(|rtype| Some(*rtype)).chain([None])
+            .find_map(|rtype| inner.get(&Key { name: name.to_lowercase(), rtype, class }, now));
+        match found {
+            Some(_) => inner.stats.hits += 1,
+            None => inner.stats.misses += 1,
+        }
+        found
+    }
+
+    /// Looks up each of `rtypes` at `name` in turn, like `get`, but also accepting entries which
+    /// have expired within the serve-stale window.
+    pub fn get_stale(&self, name: &Name, rtypes: &[Type], class: Class, now: Instant) -> Option<Cached> {
+        let mut inner = self.inner.lock().unwrap();
+        let found = rtypes.iter().map(|rtype| Some(*rtype)).chain([None])
+            .find_map(|rtype| inner.get_stale(&Key { name: name.to_lowercase(), rtype, class }, now));
+        if found.is_some() {
+            inner.stats.stale_hits += 1;
+        }
+        found
+    }
+
+    /// Takes the entries which are due to be refreshed in the background, leaving out any
+    /// already being refreshed. Each must be passed to `refreshed` once done.
+    pub fn take_due(&self) -> Vec<(Name, Type, Class)> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut taken = Vec::new();
+        for due in std::mem::take(&mut inner.due) {
+            if inner.refreshing.len() >= MAX_REFRESHING {
+                break;
+            }
+            if inner.refreshing.insert(due.clone()) {
+                taken.push(due);
+                inner.stats.prefetches += 1;
+            }
+        }
+        taken
+    }
+
+    /// Records that an entry taken by `take_due` has been refreshed, or failed to be.
+    pub fn refreshed(&self, name: &Name, rtype: Type, class: Class) {
+        self.inner.lock().unwrap().refreshing.remove(&(name.to_lowercase(), rtype, class));
+    }
+
+    /// Looks up the RRset of `rtype` at `name`, without counting it as a hit or miss.
+    pub fn get_rrset(&self, name: &Name, rtype: Type, class: Class, now: Instant) -> Option<Vec<ResourceRecord>> {
+        let key = Key { name: name.to_lowercase(), rtype: Some(rtype), class };
+        match self.inner.lock().unwrap().get(&key, now) {
+            Some(Cached::Records(records)) => Some(records),
+            _ => None,
+        }
+    }
+
+    /// Stores the RRset made up of `records`, for as long as its lowest TTL, unless a more
+    /// trustworthy copy is already cached.
+    pub fn insert_rrset(&self, records: Vec<ResourceRecord>, trust: Trust, now: Instant) {
+        let first = match records.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let key = Key {
+            name: first.name().expect("RRsets have names").to_lowercase(),
+            rtype: Some(first.rtype()),
+            class: first.class().expect("RRsets have classes"),
+        };
+        let ttl = records.iter().filter_map(ResourceRecord::ttl).min().unwrap_or(Ttl::ZERO).min(self.max_ttl);
+        self.insert(key, Cached::Records(records), ttl, trust, now);
+    }
+
+    /// Stores a denial of `rtype` at `name`, or of the whole name if `rtype` is `None`.
+    ///
+    /// As RFC 2308 §5 requires, the denial lasts for the lower of the TTL and minimum field of
+    /// the `SOA` record.
+    pub fn insert_negative(&self, name: &Name, rtype: Option<Type>, class: Class, soa: ResourceRecord, now: Instant) {
+        let minimum = match &soa {
+            ResourceRecord::SOA { ttl, minimum, .. } => (*ttl).min(Ttl::from_secs(*minimum)),
+            _ => return,
+        };
+        let key = Key { name: name.to_lowercase(), rtype, class };
+        let value = match rtype {
+            Some(_) => Cached::NoData(soa),
+            None => Cached::NoSuchDomain(soa),
+        };
+        self.insert(key, value, minimum.min(self.max_negative_ttl), Trust::Answer, now);
+    }
+
+    /// The hit, miss and eviction counts, and the current size.
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats { entries: inner.entries.len(), bytes: inner.bytes, ..inner.stats }
+    }
+
+    fn insert(&self, key: Key, value: Cached, ttl: Ttl, trust: Trust, now: Instant) {
+        let size = approximate_size(&key, &value);
+        if ttl == Ttl::ZERO || size > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.get(&key).is_some_and(|old| old.trust > trust && old.expires > now) {
+            return;
+        }
+        inner.remove(&key);
+        while inner.bytes + size > self.max_bytes {
+            let (_, oldest) = inner.recency.first_key_value().expect("full caches have entries");
+            let oldest = oldest.clone();
+            inner.remove(&oldest);
+            inner.stats.evictions += 1;
+        }
+        let used = inner.touch(&key);
+        let ttl = Duration::from(ttl);
+        inner.bytes += size;
+        inner.entries.insert(key, Entry { value, ttl, expires: now + ttl, refreshing: false, used, size, trust });
+    }
+}
+
+impl Inner {
+    /// Returns the entry for `key` if it has not expired.
+    fn get(&mut self, key: &Key, now: Instant) -> Option<Cached> {
+        let prefetch = self.prefetch;
+        let entry = self.entry(key, now)?;
+        if entry.expires <= now {
+            return None;
+        }
+        let remaining = entry.expires - now;
+        let due = prefetch && !entry.refreshing && remaining < entry.ttl / 10;
+        entry.refreshing |= due;
+        // Rounded up, so a record fetched within the last second keeps its full TTL
+        let value = entry.value.with_ttl(Ttl::from(remaining.as_secs_f64().ceil() as u32));
+        if let (true, Some(rtype)) = (due, key.rtype) {
+            self.due.push((key.name.clone(), rtype, key.class));
+        }
+        Some(value)
+    }
+
+    /// Returns the entry for `key` with the stale TTL, even if it has expired.
+    fn get_stale(&mut self, key: &Key, now: Instant) -> Option<Cached> {
+        let stale_ttl = self.stale_ttl;
+        self.entry(key, now).map(|entry| entry.value.with_ttl(stale_ttl))
+    }
+
+    /// Finds the entry for `key`, marking it as recently used. Entries too old to be served even
+    /// when stale are removed.
+    fn entry(&mut self, key: &Key, now: Instant) -> Option<&mut Entry> {
+        let entry = self.entries.get(key)?;
+        let (old, expired) = (entry.used, entry.expires + self.max_stale <= now);
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        self.recency.remove(&old);
+        let used = self.touch(key);
+        let entry = self.entries.get_mut(key).expect("entry was just found");
+        entry.used = used;
+        Some(entry)
+    }
+
+    /// Removes the entry for `key`, if there is one.
+    fn remove(&mut self, key: &Key) {
+        if let Some(old) = self.entries.remove(key) {
+            self.recency.remove(&old.used);
+            self.bytes -= old.size;
+        }
+    }
+
+    /// Records `key` as the most recently used, returning its new position.
+    fn touch(&mut self, key: &Key) -> u64 {
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.clock
+    }
+}
+
+/// Roughly how many bytes an entry takes up: its records as they would be sent, plus the
+/// bookkeeping around them.
+fn approximate_size(key: &Key, value: &Cached) -> usize {
+    let records = match value {
+        Cached::Records(records) => records.as_slice(),
+        Cached::NoData(soa) | Cached::NoSuchDomain(soa) => std::slice::from_ref(soa),
+    };
+    let wire: usize = records.iter()
+        .map(|rr| {
+            let mut cursor = Cursor::new(Vec::new());
+            rr.write_to(&mut cursor).map_or(0, |_| cursor.into_inner().len())
+        })
+        .sum();
+    // The key is held twice, by the entry and in the recency order
+    size_of::<Entry>() + 2 * (size_of::<Key>() + key.name.name.len()) + size_of_val(records) + wire
+}
+
+/// Gives `rr` a TTL of `ttl`.
+fn with_ttl(rr: &ResourceRecord, ttl: Ttl) -> ResourceRecord {
+    match rr.class() {
+        Some(class) => rr.with_class_and_ttl(class, ttl),
+        None => rr.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::Serial;
+
+    fn a(name: &str, ttl: u32) -> ResourceRecord {
+        ResourceRecord::A {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(ttl),
+            addr: [192, 0, 2, 1].into(),
+        }
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> ResourceRecord {
+        ResourceRecord::SOA {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(ttl),
+            mname: "ns1.example.com.".parse().unwrap(),
+            rname: "hostmaster.example.com.".parse().unwrap(),
+            serial: Serial(1),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum,
+        }
+    }
+
+    #[test]
+    fn entries_expire_with_their_ttl() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(60), Ttl::from_secs(3600));
+        let now = Instant::now();
+        let name: Name = "www.example.com.".parse().unwrap();
+        cache.insert_rrset(vec![a("WWW.example.com.", 300)], Trust::Answer, now);
+
+        // Capped at the maximum TTL, and aged while cached
+        let later = now + Duration::from_secs(15);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, later), Some(Cached::Records(vec![a("WWW.example.com.", 45)])));
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, now + Duration::from_secs(60)), None);
+        assert_eq!(cache.get(&name, &[Type::AAAA], Class::Internet, later), None);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2, entries: 0, ..CacheStats::default() });
+    }
+
+    #[test]
+    fn denials_last_for_the_soa_minimum() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(86400), Ttl::from_secs(3600));
+        let now = Instant::now();
+        let missing: Name = "missing.example.com.".parse().unwrap();
+        let empty: Name = "example.com.".parse().unwrap();
+        cache.insert_negative(&missing, None, Class::Internet, soa(3600, 300), now);
+        cache.insert_negative(&empty, Some(Type::A), Class::Internet, soa(100, 300), now);
+
+        let later = now + Duration::from_secs(99);
+        assert_eq!(cache.get(&missing, &[Type::MX], Class::Internet, later), Some(Cached::NoSuchDomain(soa(201, 300))));
+        assert_eq!(cache.get(&empty, &[Type::A], Class::Internet, later), Some(Cached::NoData(soa(1, 300))));
+        assert_eq!(cache.get(&empty, &[Type::A], Class::Internet, now + Duration::from_secs(100)), None);
+        assert_eq!(cache.get(&missing, &[Type::A], Class::Internet, now + Duration::from_secs(300)), None);
+    }
+
+    #[test]
+    fn expired_entries_can_be_served_stale() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(86400), Ttl::from_secs(3600))
+            .with_serve_stale(Duration::from_secs(600), Ttl::from_secs(30));
+        let now = Instant::now();
+        let name: Name = "www.example.com.".parse().unwrap();
+        cache.insert_rrset(vec![a("www.example.com.", 300)], Trust::Answer, now);
+
+        let expired = now + Duration::from_secs(400);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, expired), None);
+        assert_eq!(cache.get_stale(&name, &[Type::A], Class::Internet, expired),
+                   Some(Cached::Records(vec![a("www.example.com.", 30)])));
+        assert_eq!(cache.get_stale(&name, &[Type::A], Class::Internet, now + Duration::from_secs(900)), None);
+        assert_eq!(cache.stats().stale_hits, 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn entries_used_near_expiry_are_due_for_refresh() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(86400), Ttl::from_secs(3600)).with_prefetch(true);
+        let now = Instant::now();
+        let name: Name = "www.example.com.".parse().unwrap();
+        cache.insert_rrset(vec![a("www.example.com.", 300)], Trust::Answer, now);
+
+        cache.get(&name, &[Type::A], Class::Internet, now + Duration::from_secs(100));
+        assert!(cache.take_due().is_empty());
+        for _ in 0..2 {
+            cache.get(&name, &[Type::A], Class::Internet, now + Duration::from_secs(290));
+        }
+        assert_eq!(cache.take_due(), vec![(name, Type::A, Class::Internet)]);
+        assert!(cache.take_due().is_empty());
+        assert_eq!(cache.stats().prefetches, 1);
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        // Room for two entries of the same size
+        let key = Key { name: "one.example.".parse().unwrap(), rtype: Some(Type::A), class: Class::Internet };
+        let size = approximate_size(&key, &Cached::Records(vec![a("one.example.", 300)]));
+        let cache = Cache::new(2 * size + size / 2, Ttl::from_secs(86400), Ttl::from_secs(3600));
+        let now = Instant::now();
+        cache.insert_rrset(vec![a("one.example.", 300)], Trust::Answer, now);
+        cache.insert_rrset(vec![a("two.example.", 300)], Trust::Answer, now);
+        assert!(cache.get(&"one.example.".parse().unwrap(), &[Type::A], Class::Internet, now).is_some());
+        cache.insert_rrset(vec![a("six.example.", 300)], Trust::Answer, now);
+
+        assert!(cache.get(&"one.example.".parse().unwrap(), &[Type::A], Class::Internet, now).is_some());
+        assert!(cache.get(&"two.example.".parse().unwrap(), &[Type::A], Class::Internet, now).is_none());
+        assert!(cache.get(&"six.example.".parse().unwrap(), &[Type::A], Class::Internet, now).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().bytes, 2 * size);
+
+        // Larger RRsets take up more room, and push out more entries
+        let pair = vec![a("two.example.", 300), a("two.example.", 600)];
+        let two = "two.example.".parse().unwrap();
+        cache.insert_rrset(pair, Trust::Answer, now);
+        assert!(cache.get(&two, &[Type::A], Class::Internet, now).is_some());
+        assert!(cache.get(&"one.example.".parse().unwrap(), &[Type::A], Class::Internet, now).is_none());
+        assert!(cache.stats().evictions >= 2);
+        assert!(cache.stats().bytes <= 2 * size + size / 2);
+    }
+
+    #[test]
+    fn fresh_answers_are_not_replaced_by_less_trusted_data() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(86400), Ttl::from_secs(3600));
+        let now = Instant::now();
+        let name: Name = "ns1.example.com.".parse().unwrap();
+        let glue = |ttl| ResourceRecord::A {
+            name: name.clone(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(ttl),
+            addr: [203, 0, 113, 66].into(),
+        };
+        cache.insert_rrset(vec![a("ns1.example.com.", 300)], Trust::Answer, now);
+        cache.insert_rrset(vec![glue(600)], Trust::Additional, now);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, now), Some(Cached::Records(vec![a("ns1.example.com.", 300)])));
+
+        // Glue may replace glue, and answers which have expired
+        let later = now + Duration::from_secs(300);
+        cache.insert_rrset(vec![glue(600)], Trust::Additional, later);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, later), Some(Cached::Records(vec![glue(600)])));
+        cache.insert_rrset(vec![glue(60)], Trust::Additional, later);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, later), Some(Cached::Records(vec![glue(60)])));
+        cache.insert_rrset(vec![a("ns1.example.com.", 300)], Trust::Answer, later);
+        assert_eq!(cache.get(&name, &[Type::A], Class::Internet, later), Some(Cached::Records(vec![a("ns1.example.com.", 300)])));
+    }
+
+    #[test]
+    fn refreshes_are_limited_and_not_repeated() {
+        let cache = Cache::new(1 << 20, Ttl::from_secs(86400), Ttl::from_secs(3600)).with_prefetch(true);
+        let now = Instant::now();
+        let names: Vec<Name> = (0..MAX_REFRESHING + 2).map(|i| format!("host{i}.example.").parse().unwrap()).collect();
+        let due = |name: &Name| {
+            cache.insert_rrset(vec![a(&name.to_string(), 300)], Trust::Answer, now);
+            cache.get(name, &[Type::A], Class::Internet, now + Duration::from_secs(290));
+        };
+        names.iter().for_each(due);
+        let taken = cache.take_due();
+        assert_eq!(taken.len(), MAX_REFRESHING);
+
+        // Replaced while still being refreshed, and due again
+        due(&names[0]);
+        assert!(cache.take_due().is_empty());
+        cache.refreshed(&names[0], Type::A, Class::Internet);
+        due(&names[0]);
+        assert_eq!(cache.take_due(), vec![(names[0].clone(), Type::A, Class::Internet)]);
+        assert_eq!(cache.stats().prefetches, MAX_REFRESHING as u64 + 1);
+    }
+}
@@ -0,0 +1,131 @@
+use super::ResolvConf;
+use crate::names::Name;
+use crate::ttl::Ttl;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// The IPv4 and IPv6 addresses of the 13 root servers, `a.root-servers.net` to
+/// `m.root-servers.net`.
+const ROOT_SERVERS: [(Ipv4Addr, Ipv6Addr); 13] = [
+    (Ipv4Addr::new(198, 41, 0, 4), Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30)),
+    (Ipv4Addr::new(170, 247, 170, 2), Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb)),
+    (Ipv4Addr::new(192, 33, 4, 12), Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc)),
+    (Ipv4Addr::new(199, 7, 91, 13), Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd)),
+    (Ipv4Addr::new(192, 203, 230, 10), Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe)),
+    (Ipv4Addr::new(192, 5, 5, 241), Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf)),
+    (Ipv4Addr::new(192, 112, 36, 4), Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d)),
+    (Ipv4Addr::new(198, 97, 190, 53), Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53)),
+    (Ipv4Addr::new(192, 36, 148, 17), Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53)),
+    (Ipv4Addr::new(192, 58, 128, 30), Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30)),
+    (Ipv4Addr::new(193, 0, 14, 129), Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1)),
+    (Ipv4Addr::new(199, 7, 83, 42), Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42)),
+    (Ipv4Addr::new(202, 12, 27, 33), Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35)),
+];
+
+/// Returns the addresses of all 13 root servers: first the IPv4 addresses, then the IPv6.
+pub fn root_hints() -> Vec<IpAddr> {
+    ROOT_SERVERS.iter().map(|(v4, _)| IpAddr::V4(*v4))
+        .chain(ROOT_SERVERS.iter().map(|(_, v6)| IpAddr::V6(*v6)))
+        .collect()
+}
+
+/// Settings for a `Resolver`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverConfig {
+    /// The servers iteration begins from.
+    pub root_hints: Vec<IpAddr>,
+    /// Recursive resolvers to forward queries to, in order. If any are given, the resolver acts
+    /// as a stub and does not iterate from the root itself.
+    pub nameservers: Vec<IpAddr>,
+    /// Whether a stub resolver spreads its queries over `nameservers`, rather than always
+    /// starting with the first.
+    pub rotate: bool,
+    /// The domains appended in turn to relative names, as with the `search` line of
+    /// `resolv.conf`.
+    pub search: Vec<Name>,
+    /// How many dots a relative name needs before it is tried as it is, ahead of the search
+    /// domains.
+    pub ndots: usize,
+    /// The port queries are sent to.
+    pub port: u16,
+    /// How long to wait for a response to the first transmission of a query.
+    pub timeout: Duration,
+    /// How many times to retransmit an unanswered query. The wait doubles with each one.
+    ///
+    /// A stub resolver instead asks each of its servers once per round, and this is how many
+    /// more rounds it makes.
+    pub retries: usize,
+    /// The longest a single resolution may take, across all the queries it needs.
+    pub deadline: Duration,
+    /// The UDP payload size advertised with EDNS(0), or `None` to send queries without EDNS.
+    pub udp_payload_size: Option<u16>,
+    /// Whether to randomise the case of query names, and reject responses which do not echo it
+    /// exactly. Known as "0x20" encoding, this makes responses harder to forge.
+    pub randomize_case: bool,
+    /// Roughly how many bytes the cached RRsets and denials may take up before the least
+    /// recently used are evicted. Zero disables caching.
+    pub max_cache_bytes: usize,
+    /// The longest any record is cached for, whatever its TTL.
+    pub max_cache_ttl: Ttl,
+    /// The longest a denial is cached for, whatever its `SOA` record says.
+    pub max_negative_ttl: Ttl,
+    /// How long after expiring cached records may still be served, when the servers holding
+    /// them cannot be reached (RFC 8767). `None` disables serving stale data.
+    pub max_stale: Option<Duration>,
+    /// The TTL given to stale records when they are served.
+    pub stale_answer_ttl: Ttl,
+    /// Whether to refresh cached records in the background when they are used shortly before
+    /// expiring, so that popular names never miss the cache.
+    ///
+    /// A `Resolver` refreshes them one at a time on a single thread, shared by its clones, while
+    /// an `AsyncResolver` spawns a task for each. Only a few are ever waiting at once.
+    pub prefetch: bool,
+    /// Whether to send each zone only as much of the query name as it needs to see, as
+    /// described in RFC 9156.
+    pub qname_minimisation: bool,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> ResolverConfig {
+        ResolverConfig {
+            root_hints: root_hints(),
+            nameservers: Vec::new(),
+            rotate: false,
+            search: Vec::new(),
+            ndots: 1,
+            port: 53,
+            timeout: Duration::from_millis(800),
+            retries: 2,
+            deadline: Duration::from_secs(10),
+            // Avoids fragmentation on almost all paths, as recommended by DNS Flag Day 2020
+            udp_payload_size: Some(1232),
+            randomize_case: false,
+            max_cache_bytes: 8 << 20,
+            max_cache_ttl: Ttl::from_secs(86_400),
+            // The upper end of the range recommended by RFC 2308 §5
+            max_negative_ttl: Ttl::from_secs(10_800),
+            max_stale: None,
+            // As recommended by RFC 8767 §4
+            stale_answer_ttl: Ttl::from_secs(30),
+            prefetch: false,
+            qname_minimisation: false,
+        }
+    }
+}
+
+/// Configures a stub resolver, which forwards queries to the servers in `conf`.
+impl From<&ResolvConf> for ResolverConfig {
+    fn from(conf: &ResolvConf) -> ResolverConfig {
+        let servers = conf.nameservers.len().max(1) as u32;
+        ResolverConfig {
+            nameservers: conf.nameservers.clone(),
+            rotate: conf.rotate,
+            search: conf.search.clone(),
+            ndots: conf.ndots,
+            timeout: conf.timeout,
+            retries: conf.attempts.saturating_sub(1),
+            deadline: conf.timeout * servers * conf.attempts.max(1) as u32,
+            ..ResolverConfig::default()
+        }
+    }
+}
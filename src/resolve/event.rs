@@ -0,0 +1,79 @@
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::QType;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// A step taken by a `Resolver` while resolving a name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveEvent<'a> {
+    /// A query was sent to `server`, over TCP if `tcp` is set. Retransmissions are counted by
+    /// `attempt`, starting at 1.
+    QuerySent {
+        /// The address and port the query was sent to.
+        server: SocketAddr,
+        /// The name asked about, which may be shortened by QNAME minimisation.
+        qname: &'a Name,
+        /// The type of records asked for.
+        qtype: QType,
+        /// Whether the query was sent over TCP rather than UDP.
+        tcp: bool,
+        /// The number of times this query has been sent to `server`, including this one.
+        attempt: usize,
+    },
+    /// `server` responded to a query.
+    ResponseReceived {
+        /// The address and port the response came from.
+        server: SocketAddr,
+        /// The response, as received.
+        response: &'a Message,
+    },
+    /// A server referred the resolver to `nameserver`, one of the servers for `zone`.
+    ReferralFollowed {
+        /// The zone delegated by the referral.
+        zone: &'a Name,
+        /// The name server which will be asked next.
+        nameserver: &'a Name,
+    },
+    /// The address of `nameserver` was taken from the additional section of a referral.
+    GlueUsed {
+        /// The name server whose address was given.
+        nameserver: &'a Name,
+        /// The address given for it.
+        addr: IpAddr,
+    },
+}
+
+impl fmt::Display for ResolveEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveEvent::QuerySent { server, qname, qtype, tcp, attempt } =>
+                write!(f, "querying {server} for {qname} {qtype:?} over {} (attempt {attempt})",
+                       if *tcp { "TCP" } else { "UDP" }),
+            ResolveEvent::ResponseReceived { server, response } =>
+                write!(f, "response from {server}: {:?} with {} answers, {} authorities, {} additionals",
+                       response.rcode(),
+                       response.answers.len(),
+                       response.authorities.len(),
+                       response.additionals.len()),
+            ResolveEvent::ReferralFollowed { zone, nameserver } =>
+                write!(f, "referred to {nameserver} for {zone}"),
+            ResolveEvent::GlueUsed { nameserver, addr } =>
+                write!(f, "using glue address {addr} for {nameserver}"),
+        }
+    }
+}
+
+/// Receives the events produced by a `Resolver`.
+///
+/// Closures taking a `&ResolveEvent` implement this trait.
+pub trait ResolveObserver: Send + Sync {
+    /// Called for each step of a resolution, in order.
+    fn event(&self, event: &ResolveEvent);
+}
+
+impl<F> ResolveObserver for F where F: Fn(&ResolveEvent) + Send + Sync {
+    fn event(&self, event: &ResolveEvent) {
+        self(event)
+    }
+}
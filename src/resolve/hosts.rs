@@ -0,0 +1,212 @@
+use crate::names::{Name, RelativeName};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where static host names are listed on Unix systems.
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/// The names and addresses listed in a `hosts(5)` file, which take the place of DNS for those
+/// names.
+///
+/// Each line holds an address followed by the canonical name of the host and any aliases.
+/// Names are matched without regard to case, and lines which cannot be understood are ignored.
+///
+/// ```
+/// # use martin::resolve::Hosts;
+/// let hosts = Hosts::parse("192.0.2.1 printer.example.com printer # upstairs\n");
+/// assert_eq!(hosts.lookup(&"PRINTER.".parse().unwrap()), ["192.0.2.1".parse::<std::net::IpAddr>().unwrap()]);
+/// assert_eq!(hosts.reverse_lookup("192.0.2.1".parse().unwrap()), ["printer.example.com.".parse().unwrap()]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hosts {
+    /// The addresses of each name, canonical or alias, keyed in lowercase.
+    addrs: HashMap<Name, Vec<IpAddr>>,
+    /// The canonical names of each address.
+    names: HashMap<IpAddr, Vec<Name>>,
+}
+
+impl Hosts {
+    /// Reads the system hosts file, `/etc/hosts`.
+    pub fn system() -> io::Result<Hosts> {
+        Hosts::load(HOSTS_PATH)
+    }
+
+    /// Reads the hosts file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Hosts> {
+        Ok(Hosts::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses the contents of a hosts file.
+    pub fn parse(contents: &str) -> Hosts {
+        let mut hosts = Hosts::default();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let addr = match words.next().and_then(|addr| addr.parse::<IpAddr>().ok()) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let names: Vec<Name> = words
+                .filter_map(|name| name.parse::<RelativeName>().ok())
+                .map(|name| name.to_absolute())
+                .collect();
+            if let Some(canonical) = names.first() {
+                hosts.names.entry(addr).or_default().push(canonical.clone());
+            }
+            for name in names {
+                let addrs = hosts.addrs.entry(name.to_lowercase()).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        hosts
+    }
+
+    /// The addresses listed for `name`, in the order they appear.
+    pub fn lookup(&self, name: &Name) -> &[IpAddr] {
+        self.addrs.get(&name.to_lowercase()).map_or(&[], Vec::as_slice)
+    }
+
+    /// The canonical names listed for `addr`, in the order they appear.
+    pub fn reverse_lookup(&self, addr: IpAddr) -> &[Name] {
+        self.names.get(&addr).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns `true` if no hosts are listed.
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+}
+
+/// How often a hosts file is checked for changes.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The hosts a `Resolver` consults before DNS, which are read again from their file when it is
+/// found to have been modified.
+#[derive(Debug)]
+pub(crate) struct HostsFile {
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+#[derive(Debug, Default)]
+struct Loaded {
+    hosts: Arc<Hosts>,
+    /// When the file was modified, as of the last check.
+    modified: Option<SystemTime>,
+    /// When the file was last checked.
+    checked: Option<Instant>,
+}
+
+impl HostsFile {
+    /// Serves `hosts`, which never change.
+    pub fn fixed(hosts: Hosts) -> HostsFile {
+        HostsFile { path: None, loaded: Mutex::new(Loaded { hosts: Arc::new(hosts), ..Loaded::default() }) }
+    }
+
+    /// Serves the hosts in the file at `path`, which need not exist yet.
+    pub fn open(path: PathBuf) -> HostsFile {
+        let file = HostsFile { path: Some(path), loaded: Mutex::default() };
+        file.check(Instant::now());
+        file
+    }
+
+    /// The hosts as of the last check, without touching the file.
+    pub fn current(&self) -> Arc<Hosts> {
+        self.loaded.lock().unwrap().hosts.clone()
+    }
+
+    /// Checks the file for changes if it has not been checked within the last few seconds.
+    pub fn check(&self, now: Instant) {
+        if self.due(now) {
+            self.reload();
+        }
+    }
+
+    /// Returns `true` if the file has not been checked within the last few seconds, in which case
+    /// it counts as checked now and the caller should `reload` it.
+    pub fn due(&self, now: Instant) -> bool {
+        let mut loaded = self.loaded.lock().unwrap();
+        let due = self.path.is_some() && loaded.checked.is_none_or(|checked| now >= checked + RECHECK_INTERVAL);
+        if due {
+            loaded.checked = Some(now);
+        }
+        due
+    }
+
+    /// Reads the file again if it has been modified since it was last read. The lock is not held
+    /// while the file is read, so lookups are never held up by it.
+    pub fn reload(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        if modified == self.loaded.lock().unwrap().modified {
+            return;
+        }
+        let hosts = Hosts::load(path).unwrap_or_else(|e| {
+            log::debug!("no hosts read from {}: {e}", path.display());
+            Hosts::default()
+        });
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.hosts = Arc::new(hosts);
+        loaded.modified = modified;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_aliases_and_comments() {
+        let hosts = Hosts::parse("\
+# The loopback addresses
+127.0.0.1	localhost
+::1		localhost ip6-localhost
+192.0.2.10 server.example.com server   # the file server
+192.0.2.11 server.example.com
+not-an-address ignored.example.com
+192.0.2.12
+");
+        let name = |name: &str| name.parse::<Name>().unwrap();
+        let addr = |addr: &str| addr.parse::<IpAddr>().unwrap();
+        assert_eq!(hosts.lookup(&name("localhost.")), [addr("127.0.0.1"), addr("::1")]);
+        assert_eq!(hosts.lookup(&name("Server.")), [addr("192.0.2.10")]);
+        assert_eq!(hosts.lookup(&name("server.example.com.")), [addr("192.0.2.10"), addr("192.0.2.11")]);
+        assert!(hosts.lookup(&name("ignored.example.com.")).is_empty());
+        assert_eq!(hosts.reverse_lookup(addr("::1")), [name("localhost.")]);
+        assert_eq!(hosts.reverse_lookup(addr("192.0.2.10")), [name("server.example.com.")]);
+        assert!(hosts.reverse_lookup(addr("192.0.2.12")).is_empty());
+    }
+
+    #[test]
+    fn files_are_read_again_when_modified() {
+        let path = std::env::temp_dir().join(format!("martin-hosts-{}", std::process::id()));
+        fs::write(&path, "192.0.2.1 printer\n").unwrap();
+        let file = HostsFile::open(path.clone());
+        let now = Instant::now();
+        let printer: Name = "printer.".parse().unwrap();
+        assert_eq!(file.current().lookup(&printer), ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+
+        fs::write(&path, "192.0.2.2 printer\n").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        // Not checked again so soon
+        file.check(now + RECHECK_INTERVAL / 2);
+        assert_eq!(file.current().lookup(&printer), ["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        file.check(now + RECHECK_INTERVAL);
+        assert_eq!(file.current().lookup(&printer), ["192.0.2.2".parse::<IpAddr>().unwrap()]);
+
+        fs::remove_file(&path).unwrap();
+        file.check(now + 2 * RECHECK_INTERVAL);
+        assert!(file.current().is_empty());
+    }
+}
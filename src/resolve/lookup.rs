@@ -0,0 +1,131 @@
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::{QType, Question};
+use crate::rr::{ResourceRecord, Type};
+use crate::ttl::Ttl;
+
+/// The outcome of looking up the records of one type for a name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lookup {
+    /// The question that was asked.
+    pub question: Question,
+    /// The name holding the records, after following any aliases from the question name.
+    pub canonical_name: Name,
+    /// The answer section of the final response, including any aliases.
+    pub answers: Vec<ResourceRecord>,
+    /// The authority section of the final response. Holds the `SOA` record of negative answers.
+    pub authorities: Vec<ResourceRecord>,
+}
+
+impl Lookup {
+    /// Builds a `Lookup` from the response which answered `question`.
+    pub(crate) fn from_response(question: Question, response: Message) -> Lookup {
+        Lookup::from_records(question, response.answers, response.authorities)
+    }
+
+    /// Builds a `Lookup` from the answer and authority records for `question`.
+    pub(crate) fn from_records(question: Question,
+                               answers: Vec<ResourceRecord>,
+                               authorities: Vec<ResourceRecord>)
+                               -> Lookup {
+        let canonical_name = canonical_name(&question, &answers);
+        Lookup { question, canonical_name, answers, authorities }
+    }
+
+    /// The records answering the question: those of the requested type, owned by the canonical
+    /// name.
+    pub fn records(&self) -> impl Iterator<Item=&ResourceRecord> {
+        self.answers.iter()
+            .filter(move |rr| rr.name().is_some_and(|n| n.eq_ignore_case(&self.canonical_name)))
+            .filter(move |rr| match self.question.qtype {
+                QType::ByType(t) => rr.rtype() == t,
+                _ => true,
+            })
+    }
+
+    /// The `CNAME` and `DNAME` records followed from the question name to the canonical name, in
+    /// order.
+    pub fn aliases(&self) -> impl Iterator<Item=&ResourceRecord> {
+        self.answers.iter().filter(|rr| matches!(rr.rtype(), Type::CNAME | Type::DNAME))
+    }
+
+    /// Returns `true` if no records of the requested type exist.
+    pub fn is_empty(&self) -> bool {
+        self.records().next().is_none()
+    }
+
+    /// The lowest TTL among the answers, which is how long the whole answer may be cached for.
+    pub fn ttl(&self) -> Option<Ttl> {
+        self.answers.iter().filter_map(ResourceRecord::ttl).min()
+    }
+}
+
+/// Follows the chain of `CNAME` and `DNAME` records in `answers`, starting from the question
+/// name.
+fn canonical_name(question: &Question, answers: &[ResourceRecord]) -> Name {
+    alias_chain(question, answers).0.pop().expect("chains start with the question name")
+}
+
+/// Follows the chain of `CNAME` and `DNAME` records in `answers`, starting from the question
+/// name. Returns the names along the way, ending with the canonical name, and the aliases
+/// followed.
+pub(crate) fn alias_chain<'a>(question: &Question, answers: &'a [ResourceRecord]) -> (Vec<Name>, Vec<&'a ResourceRecord>) {
+    let mut names = vec![question.qname.clone()];
+    let mut aliases = Vec::new();
+    if question.qtype == QType::ByType(Type::CNAME) {
+        return (names, aliases);
+    }
+    // Each alias can only be followed once, which stops loops
+    for _ in 0..answers.len() {
+        match next_alias(names.last().expect("chains start with the question name"), answers) {
+            Some((alias, target)) => {
+                aliases.push(alias);
+                names.push(target);
+            }
+            None => break,
+        }
+    }
+    (names, aliases)
+}
+
+/// Finds the alias in `answers` which redirects `name`, returning it with the name it leads to.
+///
+/// A `CNAME` owned by `name` is preferred. Otherwise, a `DNAME` owned by an ancestor of `name`
+/// is used to synthesize the target.
+pub(crate) fn next_alias<'a>(name: &Name, answers: &'a [ResourceRecord]) -> Option<(&'a ResourceRecord, Name)> {
+    let cname = answers.iter().find_map(|rr| match rr {
+        ResourceRecord::CNAME { name: owner, cname, .. } if owner.eq_ignore_case(name) => Some((rr, cname.clone())),
+        _ => None,
+    });
+    cname.or_else(|| answers.iter().find_map(|rr| match rr {
+        ResourceRecord::DNAME { name: owner, target, .. } if !owner.eq_ignore_case(name) =>
+            name.replace_suffix(owner, target).map(|synthesized| (rr, synthesized)),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rr::Class;
+
+    #[test]
+    fn dname_targets_are_synthesized() {
+        let question = Question::new("www.old.example.", QType::ByType(Type::A)).unwrap();
+        let answers = vec![
+            ResourceRecord::DNAME {
+                name: "old.example.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(60),
+                target: "new.example.".parse().unwrap(),
+            },
+            ResourceRecord::CNAME {
+                name: "www.new.example.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(60),
+                cname: "host.example.".parse().unwrap(),
+            },
+        ];
+        assert_eq!(canonical_name(&question, &answers), "host.example.".parse().unwrap());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::names::Name;
+
+/// The most queries sent for a single name with a minimised query name (RFC 9156 §2.3).
+pub(crate) const MAX_MINIMISE_COUNT: usize = 10;
+
+/// How many of those queries reveal just one label at a time.
+const MINIMISE_ONE_LAB: usize = 4;
+
+/// Chooses the names to send in place of a query name, revealing it a label or so at a time as
+/// described in RFC 9156.
+#[derive(Debug, Clone)]
+pub(crate) struct Minimiser {
+    qname: Name,
+    /// The longest name sent so far.
+    revealed: Name,
+    count: usize,
+}
+
+impl Minimiser {
+    /// Starts minimising `qname`.
+    pub fn new(qname: &Name) -> Minimiser {
+        Minimiser { qname: qname.clone(), revealed: Name { name: vec![0] }, count: 0 }
+    }
+
+    /// The name to ask the servers for `zone_cut` about next, or `None` once the full query name
+    /// should be sent.
+    pub fn next(&mut self, zone_cut: &Name) -> Option<Name> {
+        if self.count == MAX_MINIMISE_COUNT {
+            return None;
+        }
+        let known = if self.revealed.is_subdomain_of(zone_cut) { &self.revealed } else { zone_cut };
+        let remaining = label_count(&self.qname).checked_sub(label_count(known))?;
+        let add = if self.count < MINIMISE_ONE_LAB {
+            1
+        } else {
+            (remaining / (MAX_MINIMISE_COUNT - self.count)).max(1)
+        };
+        if add >= remaining {
+            return None;
+        }
+        let mut name = self.qname.clone();
+        for _ in 0..remaining - add {
+            name = name.parent()?;
+        }
+        self.count += 1;
+        self.revealed = name.clone();
+        Some(name)
+    }
+}
+
+fn label_count(name: &Name) -> usize {
+    let mut count = 0;
+    let mut current = name.clone();
+    while let Some(parent) = current.parent() {
+        count += 1;
+        current = parent;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_revealed_one_at_a_time_then_faster() {
+        let qname: Name = "a.b.c.d.e.f.g.h.i.j.k.l.m.n.o.p.example.com.".parse().unwrap();
+        let mut minimiser = Minimiser::new(&qname);
+        let root = Name { name: vec![0] };
+        let mut sent = Vec::new();
+        while let Some(name) = minimiser.next(&root) {
+            sent.push(name.to_string());
+        }
+        assert_eq!(sent, [
+            "com.",
+            "example.com.",
+            "p.example.com.",
+            "o.p.example.com.",
+            "m.n.o.p.example.com.",
+            "k.l.m.n.o.p.example.com.",
+            "i.j.k.l.m.n.o.p.example.com.",
+            "g.h.i.j.k.l.m.n.o.p.example.com.",
+            "d.e.f.g.h.i.j.k.l.m.n.o.p.example.com.",
+        ]);
+
+        // Referrals skip ahead
+        let mut minimiser = Minimiser::new(&"www.example.com.".parse().unwrap());
+        assert_eq!(minimiser.next(&"example.com.".parse().unwrap()), None);
+    }
+}
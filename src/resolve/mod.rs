@@ -0,0 +1,2130 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use crate::header::{Rcode};
+use crate::message::Message;
+use crate::names::{Name, NameParseError, RelativeName};
+use crate::question::{QType, Question};
+use crate::random::{self, random_u16};
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::ttl::Ttl;
+
+#[cfg(feature = "async")]
+mod async_resolver;
+mod cache;
+mod config;
+mod event;
+mod hosts;
+mod lookup;
+mod minimise;
+mod network;
+mod resolv_conf;
+mod srtt;
+mod task;
+mod transport;
+
+#[cfg(feature = "async")]
+pub use self::async_resolver::AsyncResolver;
+pub use self::cache::CacheStats;
+pub use self::config::{root_hints, ResolverConfig};
+pub use self::event::{ResolveEvent, ResolveObserver};
+pub use self::hosts::{Hosts, HOSTS_PATH};
+pub use self::lookup::Lookup;
+pub use self::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+pub use self::transport::{MemoryTransport, TcpTransport, Transport, UdpTransport};
+use self::cache::{Cache, Cached, Trust};
+use self::hosts::HostsFile;
+use self::minimise::{Minimiser, MAX_MINIMISE_COUNT};
+use self::network::{Network, Transports};
+use self::srtt::RttTable;
+use self::task::{block_on, join_unless};
+
+const MAX_LOOKUPS: usize = 20;
+
+/// The most `CNAME` and `DNAME` records followed while looking up a single name.
+const MAX_ALIASES: usize = 8;
+
+/// Resolves `host` to its addresses using a `Resolver` with the default configuration.
+pub fn resolve(host: &str) -> Result<Vec<IpAddr>, ResolveError> {
+    Resolver::default().resolve(host)
+}
+
+/// An iterative resolver, which follows referrals from the root servers down to the servers
+/// authoritative for a name.
+///
+/// Configured with `nameservers`, it is instead a stub resolver, which asks those recursive
+/// resolvers to do the work. `Resolver::system` creates one from `/etc/resolv.conf`.
+///
+/// Each step is logged through the `log` crate, and may also be passed to an observer.
+///
+/// Answers are cached for as long as their TTLs allow. Clones of a resolver share its cache.
+///
+/// Names and addresses listed in a hosts file, given with `with_hosts_file`, are answered from
+/// it without sending any queries.
+///
+/// Lookups block the calling thread. With the `async` feature, `AsyncResolver` takes the same
+/// steps without blocking, for use with tokio.
+#[derive(Clone)]
+pub struct Resolver {
+    config: ResolverConfig,
+    observer: Option<Arc<dyn ResolveObserver>>,
+    hosts: Option<Arc<HostsFile>>,
+    transports: Transports,
+    rtt: Arc<RttTable>,
+    cache: Arc<Cache>,
+    /// Where a rotating stub resolver starts in its list of servers.
+    next_server: Arc<AtomicUsize>,
+    /// Sends questions to the thread which refreshes them, started with the first prefetch.
+    prefetcher: Arc<OnceLock<Sender<(Resolver, Question)>>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new(ResolverConfig::default())
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolver")
+            .field("config", &self.config)
+            .field("observer", &self.observer.is_some())
+            .field("hosts", &self.hosts)
+            .field("transports", &self.transports)
+            .field("rtt", &self.rtt)
+            .field("cache", &self.cache.stats())
+            .finish()
+    }
+}
+
+impl Resolver {
+    /// Creates a `Resolver` with the given configuration.
+    pub fn new(config: ResolverConfig) -> Resolver {
+        let cache = Cache::new(config.max_cache_bytes, config.max_cache_ttl, config.max_negative_ttl)
+            .with_serve_stale(config.max_stale.unwrap_or(Duration::ZERO), config.stale_answer_ttl)
+            .with_prefetch(config.prefetch);
+        Resolver {
+            config,
+            observer: None,
+            hosts: None,
+            transports: Transports::default(),
+            rtt: Arc::default(),
+            cache: Arc::new(cache),
+            next_server: Arc::default(),
+            prefetcher: Arc::default(),
+        }
+    }
+
+    /// Creates a stub resolver which uses the recursive resolvers in `/etc/resolv.conf`, after
+    /// the hosts listed in `/etc/hosts`.
+    pub fn system() -> io::Result<Resolver> {
+        Ok(Resolver::new(ResolverConfig::from(&ResolvConf::system()?)).with_hosts_file(HOSTS_PATH))
+    }
+
+    /// Passes every step this resolver takes to `observer`.
+    pub fn with_observer<O: ResolveObserver + 'static>(mut self, observer: O) -> Resolver {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Answers lookups for the names and addresses in `hosts` without querying DNS.
+    pub fn with_hosts(mut self, hosts: Hosts) -> Resolver {
+        self.hosts = Some(Arc::new(HostsFile::fixed(hosts)));
+        self
+    }
+
+    /// Answers lookups for the names and addresses in the hosts file at `path` without querying
+    /// DNS. The file is checked for changes every few seconds, and read again once modified.
+    pub fn with_hosts_file<P: Into<PathBuf>>(mut self, path: P) -> Resolver {
+        self.hosts = Some(Arc::new(HostsFile::open(path.into())));
+        self
+    }
+
+    /// Sends queries through `transport`, rather than over UDP and then TCP.
+    ///
+    /// Only blocking lookups use it: an `AsyncResolver` always uses tokio's sockets.
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> Resolver {
+        let transport: Arc<dyn Transport> = Arc::new(transport);
+        self.transports = Transports { udp: transport.clone(), tcp: transport };
+        self
+    }
+
+    /// The configuration of this resolver.
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// The smoothed round-trip time of the server at `addr`, or `None` if it has never been
+    /// queried. Timeouts count as long round trips.
+    ///
+    /// Servers are tried in order of this time, and clones of a resolver share their
+    /// measurements.
+    pub fn smoothed_rtt(&self, addr: IpAddr) -> Option<Duration> {
+        self.rtt.get(addr)
+    }
+
+    /// How often lookups have been answered from the cache, and how full it is.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Resolves `host` to its IPv4 and IPv6 addresses.
+    ///
+    /// Fails with `ResolveError::DeadlineExceeded` if this takes longer than the configured
+    /// deadline, including the time spent resolving the names of name servers.
+    ///
+    /// Relative names are expanded through the search list, as described for `search_names`.
+    pub fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        self.check_hosts();
+        let addrs = block_on(self.resolve_on(&self.transports, host));
+        self.prefetch();
+        addrs
+    }
+
+    /// Looks up the records of type `qtype` and class `class` for `name`.
+    ///
+    /// Names which exist without records of the type give an empty `Lookup`, while names which
+    /// do not exist fail with `ResolveError::NoSuchDomain`. Relative names are expanded through
+    /// the search list.
+    pub fn lookup(&self, name: &str, qtype: QType, class: Class) -> Result<Lookup, ResolveError> {
+        self.check_hosts();
+        let lookup = block_on(self.lookup_on(&self.transports, name, qtype, class));
+        self.prefetch();
+        lookup
+    }
+
+    /// The names tried, in order, when looking up `host`.
+    ///
+    /// As with glibc, a name ending in a '.' is only tried as it is. Other names are tried with
+    /// each search domain appended, and then as they are, unless they have at least `ndots`
+    /// dots, in which case they are tried as they are first.
+    pub fn search_names(&self, host: &str) -> Result<Vec<Name>, ResolveError> {
+        let host: RelativeName = host.parse()?;
+        if host.is_absolute() {
+            return Ok(vec![host.to_absolute()]);
+        }
+        let mut names: Vec<Name> = self.config.search.iter()
+            .filter_map(|domain| host.append(domain).ok())
+            .collect();
+        if host.ndots() >= self.config.ndots {
+            names.insert(0, host.to_absolute());
+        } else {
+            names.push(host.to_absolute());
+        }
+        Ok(names)
+    }
+
+    /// Looks up the IPv6 addresses of `name`.
+    pub fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, ResolveError> {
+        Ok(self.lookup_type(name, Type::AAAA)?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::AAAA { addr, .. } => Some(*addr),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Looks up the mail exchanges for `name`, as (preference, exchange) pairs ordered from the
+    /// most preferred.
+    pub fn lookup_mx(&self, name: &str) -> Result<Vec<(u16, Name)>, ResolveError> {
+        let mut exchanges: Vec<_> = self.lookup_type(name, Type::MX)?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::MX { preference, exchange, .. } => Some((*preference, exchange.clone())),
+                _ => None,
+            })
+            .collect();
+        exchanges.sort_by_key(|(preference, _)| *preference);
+        Ok(exchanges)
+    }
+
+    /// Looks up the text records for `name`. The strings making up each record are joined, and
+    /// bytes which are not UTF-8 replaced.
+    pub fn lookup_txt(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        Ok(self.lookup_type(name, Type::TXT)?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::TXT { data, .. } => Some(String::from_utf8_lossy(&data.concat()).into_owned()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Looks up the name servers for the zone `name`.
+    pub fn lookup_ns(&self, name: &str) -> Result<Vec<Name>, ResolveError> {
+        Ok(self.lookup_type(name, Type::NS)?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::NS { ns_name, .. } => Some(ns_name.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Looks up the `SOA` record of the zone `name`. Names which are not the apex of a zone have
+    /// none.
+    pub fn lookup_soa(&self, name: &str) -> Result<Option<ResourceRecord>, ResolveError> {
+        Ok(self.lookup_type(name, Type::SOA)?.records().next().cloned())
+    }
+
+    /// Looks up the `SRV` records for `name`, ordered by priority.
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<ResourceRecord>, ResolveError> {
+        let mut records: Vec<_> = self.lookup_type(name, Type::SRV)?.records().cloned().collect();
+        records.sort_by_key(|rr| match rr {
+            ResourceRecord::SRV { priority, .. } => *priority,
+            _ => u16::MAX,
+        });
+        Ok(records)
+    }
+
+    /// Looks up the names `name` points to; usually the host name for a reverse lookup.
+    pub fn lookup_ptr(&self, name: &str) -> Result<Vec<Name>, ResolveError> {
+        Ok(self.lookup_type(name, Type::PTR)?.records()
+            .filter_map(|rr| match rr {
+                ResourceRecord::PTR { ptrname, .. } => Some(ptrname.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Looks up the host names of `addr`, from the `PTR` records under `in-addr.arpa.` or
+    /// `ip6.arpa.`.
+    pub fn reverse_lookup(&self, addr: IpAddr) -> Result<Vec<Name>, ResolveError> {
+        self.lookup_ptr(&Name::reverse_from_ip(addr).to_string())
+    }
+
+    fn lookup_type(&self, name: &str, rtype: Type) -> Result<Lookup, ResolveError> {
+        self.lookup(name, QType::ByType(rtype), Class::Internet)
+    }
+
+    async fn resolve_on<N: Network>(&self, net: &N, host: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        let deadline = Instant::now() + self.config.deadline;
+        self.search(host, |name| self.resolve_before(net, name, deadline)).await
+    }
+
+    async fn lookup_on<N: Network>(&self, net: &N, name: &str, qtype: QType, class: Class) -> Result<Lookup, ResolveError> {
+        let deadline = Instant::now() + self.config.deadline;
+        self.search(name, |qname| self.lookup_before(net, Question { qname, qtype, qclass: class }, deadline)).await
+    }
+
+    /// Tries each of the search names for `host` until one of them exists.
+    async fn search<T, F, R>(&self, host: &str, mut lookup: F) -> Result<T, ResolveError>
+        where F: FnMut(Name) -> R, R: Future<Output=Result<T, ResolveError>>
+    {
+        let mut names = self.search_names(host)?;
+        // As with libc, a name in the hosts file is found before any query is sent
+        if let Some(hosts) = &self.hosts {
+            let hosts = hosts.current();
+            names.sort_by_key(|name| hosts.lookup(name).is_empty());
+        }
+        let mut result = Err(ResolveError::NoSuchDomain);
+        for name in names {
+            log::debug!("trying {name}");
+            result = lookup(name).await;
+            if !matches!(result, Err(ResolveError::NoSuchDomain)) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Looks up both the IPv4 and IPv6 addresses of `host`, concurrently where the network
+    /// allows. Only one of the lookups needs to succeed.
+    async fn resolve_before<N: Network>(&self, net: &N, host: Name, deadline: Instant) -> Result<Vec<IpAddr>, ResolveError> {
+        let question = |rtype| Question { qname: host.clone(), qtype: QType::ByType(rtype), qclass: Class::Internet };
+        let (v4, v6) = join_unless(
+            self.lookup_before(net, question(Type::A), deadline),
+            self.lookup_before(net, question(Type::AAAA), deadline),
+            // Not worth waiting for if the name does not exist, or time has run out
+            |v4| matches!(v4, Err(ResolveError::NoSuchDomain | ResolveError::DeadlineExceeded(_))),
+        ).await;
+        let lookups = match (v4, v6) {
+            (Err(e), None | Some(Err(_))) => return Err(e),
+            (v4, v6) => v4.into_iter().chain(v6.into_iter().flatten()),
+        };
+        Ok(lookups
+            .flat_map(|lookup| lookup.records()
+                .filter_map(|rr| match rr {
+                    ResourceRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+                    ResourceRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>())
+            .collect())
+    }
+
+    /// Looks up `question`, restarting from the root for the target of any alias which the
+    /// servers for the original name could not answer for.
+    async fn lookup_before<N: Network>(&self, net: &N, question: Question, deadline: Instant) -> Result<Lookup, ResolveError> {
+        if let Some(lookup) = self.answer_from_hosts(&question) {
+            return Ok(lookup);
+        }
+        let mut aliases: Vec<ResourceRecord> = Vec::new();
+        let mut seen = vec![question.qname.clone()];
+        let mut current = question.clone();
+        loop {
+            let lookup = self.iterate(net, current.clone(), deadline).await?;
+            let mut name = current.qname.clone();
+            if question.qtype != QType::ByType(Type::CNAME) {
+                while let Some((alias, target)) = lookup::next_alias(&name, &lookup.answers) {
+                    if seen.iter().any(|n| n.eq_ignore_case(&target)) {
+                        return Err(ResolveError::AliasLoop(target));
+                    }
+                    if aliases.len() == MAX_ALIASES {
+                        return Err(ResolveError::TooManyAliases(MAX_ALIASES));
+                    }
+                    log::debug!("{name} is an alias for {target}");
+                    aliases.push(alias.clone());
+                    seen.push(target.clone());
+                    name = target;
+                }
+            }
+            let answered = lookup.answers.iter()
+                .any(|rr| rr.name().is_some_and(|n| n.eq_ignore_case(&name)) && !is_alias(rr));
+            if name.eq_ignore_case(&current.qname) || answered {
+                let rest: Vec<_> = lookup.answers.into_iter().filter(|rr| !aliases.contains(rr)).collect();
+                aliases.extend(rest);
+                return Ok(Lookup {
+                    question,
+                    canonical_name: name,
+                    answers: aliases,
+                    authorities: lookup.authorities,
+                });
+            }
+            current.qname = name;
+        }
+    }
+
+    /// Reads the hosts file again if it has been modified, checking at most every few seconds.
+    fn check_hosts(&self) {
+        if let Some(hosts) = &self.hosts {
+            hosts.check(Instant::now());
+        }
+    }
+
+    /// Answers `question` from the hosts file, if it lists the name or address asked about.
+    fn answer_from_hosts(&self, question: &Question) -> Option<Lookup> {
+        let hosts = self.hosts.as_ref()?.current();
+        if question.qclass != Class::Internet {
+            return None;
+        }
+        let (name, class, ttl) = (question.qname.clone(), Class::Internet, Ttl::ZERO);
+        let answers: Vec<ResourceRecord> = match question.qtype {
+            QType::ByType(rtype @ (Type::A | Type::AAAA)) => {
+                let addrs = hosts.lookup(&question.qname);
+                if addrs.is_empty() {
+                    return None;
+                }
+                addrs.iter()
+                    .filter_map(|addr| match (addr, rtype) {
+                        (IpAddr::V4(addr), Type::A) => Some(ResourceRecord::A { name: name.clone(), class, ttl, addr: *addr }),
+                        (IpAddr::V6(addr), Type::AAAA) => Some(ResourceRecord::AAAA { name: name.clone(), class, ttl, addr: *addr }),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            QType::ByType(Type::PTR) => {
+                let addr = match question.qname.to_reverse_ip()? {
+                    (addr @ IpAddr::V4(_), 32) | (addr @ IpAddr::V6(_), 128) => addr,
+                    _ => return None,
+                };
+                let names = hosts.reverse_lookup(addr);
+                if names.is_empty() {
+                    return None;
+                }
+                names.iter()
+                    .map(|ptrname| ResourceRecord::PTR { name: name.clone(), class, ttl, ptrname: ptrname.clone() })
+                    .collect()
+            }
+            _ => return None,
+        };
+        log::debug!("answering {} {:?} from the hosts file", question.qname, question.qtype);
+        Some(Lookup::from_records(question.clone(), answers, Vec::new()))
+    }
+
+    /// Follows referrals for `question` from the closest zone cut in the cache, or from the root,
+    /// until a server answers it.
+    ///
+    /// Each response may only speak for the zone its server was referred for. Records outside it
+    /// are discarded, and referrals must lead further down towards the question name.
+    ///
+    /// Answers come from the cache when possible. If the servers cannot be reached, expired
+    /// answers may be served instead.
+    async fn iterate<N: Network>(&self, net: &N, question: Question, deadline: Instant) -> Result<Lookup, ResolveError> {
+        if let Some(cached) = self.cached(&question) {
+            return cached;
+        }
+        match self.iterate_uncached(net, question.clone(), deadline).await {
+            Err(error) if !matches!(error, ResolveError::NoSuchDomain) => {
+                let stale = self.cache.get_stale(&question.qname, &cache_types(&question), question.qclass, Instant::now());
+                match stale {
+                    Some(stale) => {
+                        log::debug!("serving a stale answer for {} {:?}: {error}", question.qname, question.qtype);
+                        from_cache(question, stale)
+                    }
+                    None => Err(error),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Follows referrals for `question`, without looking in the cache for an answer.
+    ///
+    /// With QNAME minimisation, each zone is only asked about one more label of the name than it
+    /// needs to refer the resolver onwards. Servers which respond badly to that are asked the
+    /// full name instead.
+    async fn iterate_uncached<N: Network>(&self, net: &N, question: Question, deadline: Instant) -> Result<Lookup, ResolveError> {
+        if !self.config.nameservers.is_empty() {
+            return self.forward(net, question, deadline).await;
+        }
+        let (mut zone_cut, mut servers) = self.closest_delegation(net, &question, deadline).await?;
+        let mut minimiser = self.config.qname_minimisation.then(|| Minimiser::new(&question.qname));
+        for _ in 0..MAX_LOOKUPS + MAX_MINIMISE_COUNT {
+            let minimised = minimiser.as_mut().and_then(|minimiser| minimiser.next(&zone_cut));
+            let reply = match minimised {
+                Some(qname) => {
+                    let asked = Question { qname, qtype: QType::ByType(Type::A), qclass: question.qclass };
+                    let reply = match self.query_zone(net, &asked, &zone_cut, &servers, deadline).await {
+                        Err(e @ ResolveError::DeadlineExceeded(_)) => return Err(e),
+                        Err(e) => {
+                            log::debug!("minimised query for {} failed, sending the full name: {e}", asked.qname);
+                            minimiser = None;
+                            continue;
+                        }
+                        Ok(reply) => reply,
+                    };
+                    // Some servers deny that empty non-terminals exist
+                    if reply.rcode() == Rcode::NameError {
+                        log::debug!("{} was denied, sending the full name", asked.qname);
+                        minimiser = None;
+                        continue;
+                    }
+                    // Not a zone cut: reveal more of the name to the same servers
+                    if !is_referral(&reply) {
+                        continue;
+                    }
+                    reply
+                }
+                None => self.query_zone(net, &question, &zone_cut, &servers, deadline).await?,
+            };
+            // Preferred case: we get a "doesn't exist" response, or an answer
+            if reply.rcode() == Rcode::NameError {
+                self.cache_reply(&question, &reply);
+                return Err(ResolveError::NoSuchDomain);
+            }
+            let (zone, nameservers) = match get_referral(&reply) {
+                Some(referral) if is_referral(&reply) => referral,
+                _ => {
+                    self.cache_reply(&question, &reply);
+                    return Ok(Lookup::from_response(question, reply));
+                }
+            };
+            self.emit(ResolveEvent::ReferralFollowed { zone: &zone, nameserver: &nameservers[0] });
+            self.cache_referral(&zone, &reply);
+            // Second best: we received the IPs of other nameservers to query. Failing that, only
+            // their names, which are resolved when they are needed.
+            servers = nameservers.into_iter()
+                .map(|ns| {
+                    let addrs = get_glue(&reply, &ns);
+                    let glue = !addrs.is_empty();
+                    Nameserver { name: Some(ns), addrs, glue }
+                })
+                .collect();
+            zone_cut = zone;
+        }
+        Err(ResolveError::ExceededMaximumLookupDepth(MAX_LOOKUPS))
+    }
+
+    /// Asks the configured recursive resolvers for `question`, going through the list of them
+    /// once per round until one gives an answer or a denial.
+    async fn forward<N: Network>(&self, net: &N, question: Question, deadline: Instant) -> Result<Lookup, ResolveError> {
+        let mut servers = self.config.nameservers.clone();
+        if self.config.rotate {
+            let start = self.next_server.fetch_add(1, Ordering::Relaxed) % servers.len();
+            servers.rotate_left(start);
+        }
+        let mut attempts = Vec::new();
+        for _ in 0..=self.config.retries {
+            for server in servers.iter() {
+                let msg = self.query_message(&question, true);
+                let result = self.exchange(net, msg, *server, 0, deadline).await.and_then(|reply| match reply.rcode() {
+                    Rcode::NoError | Rcode::NameError => Ok(reply),
+                    rcode => Err(ResolveError::ErrorResponse(rcode)),
+                });
+                match result {
+                    Ok(reply) => {
+                        self.cache_reply(&question, &reply);
+                        if reply.rcode() == Rcode::NameError {
+                            return Err(ResolveError::NoSuchDomain);
+                        }
+                        return Ok(Lookup::from_response(question, reply));
+                    }
+                    Err(e @ ResolveError::DeadlineExceeded(_)) => return Err(e),
+                    Err(error) => {
+                        log::debug!("passing over {server} for {}: {error}", question.qname);
+                        attempts.push(FailedAttempt { nameserver: None, addr: Some(*server), error });
+                    }
+                }
+            }
+        }
+        Err(ResolveError::AllServersFailed { zone: question.qname, attempts })
+    }
+
+    /// Finds the deepest zone cut above the name in `question` whose servers are cached, falling
+    /// back to the root servers.
+    async fn closest_delegation<N: Network>(&self, net: &N, question: &Question, deadline: Instant) -> Result<(Name, Vec<Nameserver>), ResolveError> {
+        let mut zone = Some(question.qname.clone());
+        while let Some(cut) = zone {
+            if let Some(servers) = self.cached_nameservers(&cut, question.qclass) {
+                log::debug!("starting from the cached servers for {cut}");
+                return Ok((cut, servers));
+            }
+            zone = cut.parent();
+        }
+        let root = Name { name: vec![0] };
+        let primed = self.cache.get_rrset(&root, Type::NS, Class::Internet, Instant::now()).is_some();
+        if !primed && self.config.max_cache_bytes > 0 && question.qclass == Class::Internet && !self.config.root_hints.is_empty() {
+            self.prime_before(net, deadline).await?;
+            if let Some(servers) = self.cached_nameservers(&root, question.qclass) {
+                return Ok((root, servers));
+            }
+        }
+        let hints = self.config.root_hints.iter()
+            .map(|hint| Nameserver { name: None, addrs: vec![*hint], glue: false })
+            .collect();
+        Ok((root, hints))
+    }
+
+    /// The cached servers for `zone`, if any of their addresses are cached too.
+    fn cached_nameservers(&self, zone: &Name, class: Class) -> Option<Vec<Nameserver>> {
+        let now = Instant::now();
+        let servers: Vec<Nameserver> = self.cache.get_rrset(zone, Type::NS, class, now)?
+            .into_iter()
+            .filter_map(|rr| match rr {
+                ResourceRecord::NS { ns_name, .. } => Some(ns_name),
+                _ => None,
+            })
+            .map(|ns| {
+                let addrs = [Type::A, Type::AAAA].into_iter()
+                    .filter_map(|rtype| self.cache.get_rrset(&ns, rtype, class, now))
+                    .flatten()
+                    .filter_map(|rr| match rr {
+                        ResourceRecord::A { addr, .. } => Some(IpAddr::V4(addr)),
+                        ResourceRecord::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                        _ => None,
+                    })
+                    .collect();
+                Nameserver { name: Some(ns), addrs, glue: false }
+            })
+            .collect();
+        if servers.iter().any(|server| !server.addrs.is_empty()) {
+            Some(servers)
+        } else {
+            None
+        }
+    }
+
+    /// Asks the root hints for the current root servers, as described in RFC 8109, and caches
+    /// them.
+    ///
+    /// This happens automatically whenever the cached set has expired. Has no lasting effect if
+    /// the cache is disabled.
+    pub fn prime(&self) -> Result<(), ResolveError> {
+        block_on(self.prime_before(&self.transports, Instant::now() + self.config.deadline))
+    }
+
+    async fn prime_before<N: Network>(&self, net: &N, deadline: Instant) -> Result<(), ResolveError> {
+        let root = Name { name: vec![0] };
+        let question = Question { qname: root.clone(), qtype: QType::ByType(Type::NS), qclass: Class::Internet };
+        let hints: Vec<Nameserver> = self.config.root_hints.iter()
+            .map(|hint| Nameserver { name: None, addrs: vec![*hint], glue: false })
+            .collect();
+        log::debug!("priming the root servers");
+        let reply = self.query_zone(net, &question, &root, &hints, deadline).await?;
+        self.cache_reply(&question, &reply);
+        let nameservers: Vec<Name> = reply.answers.iter()
+            .filter_map(|rr| match rr {
+                ResourceRecord::NS { name, ns_name, .. } if name.is_root() => Some(ns_name.clone()),
+                _ => None,
+            })
+            .collect();
+        let addrs = reply.additionals.iter()
+            .filter(|rr| rr.name().is_some_and(|name| nameservers.iter().any(|ns| ns.eq_ignore_case(name))));
+        self.cache_rrsets(addrs.filter(|rr| matches!(rr.rtype(), Type::A | Type::AAAA)), Trust::Additional);
+        Ok(())
+    }
+
+    /// Caches the servers for `zone` from a referral to it, along with their glue.
+    fn cache_referral(&self, zone: &Name, reply: &Message) {
+        let nameservers: Vec<&ResourceRecord> = reply.authorities.iter()
+            .filter(|rr| rr.rtype() == Type::NS && rr.name().is_some_and(|name| name.eq_ignore_case(zone)))
+            .collect();
+        let glue = reply.additionals.iter()
+            .filter(|rr| matches!(rr.rtype(), Type::A | Type::AAAA))
+            .filter(|rr| nameservers.iter().any(|ns| match ns {
+                ResourceRecord::NS { ns_name, .. } => rr.name().is_some_and(|name| name.eq_ignore_case(ns_name)),
+                _ => false,
+            }));
+        self.cache_rrsets(nameservers.iter().copied(), Trust::Referral);
+        self.cache_rrsets(glue, Trust::Additional);
+    }
+
+    /// Groups `records` into RRsets and caches each of them, unless a more trustworthy copy is
+    /// already cached.
+    fn cache_rrsets<'a, I>(&self, records: I, trust: Trust) where I: IntoIterator<Item=&'a ResourceRecord> {
+        let now = Instant::now();
+        let mut rrsets: Vec<Vec<ResourceRecord>> = Vec::new();
+        for rr in records {
+            let rrset = rrsets.iter_mut().find(|rrset| {
+                rrset[0].rtype() == rr.rtype() && rrset[0].class() == rr.class()
+                    && rrset[0].name().zip(rr.name()).is_some_and(|(a, b)| a.eq_ignore_case(b))
+            });
+            match rrset {
+                Some(rrset) => rrset.push(rr.clone()),
+                None => rrsets.push(vec![rr.clone()]),
+            }
+        }
+        for rrset in rrsets {
+            self.cache.insert_rrset(rrset, trust, now);
+        }
+    }
+
+    /// Answers `question` from the cache, if it holds the records, an alias for the name, or a
+    /// denial.
+    fn cached(&self, question: &Question) -> Option<Result<Lookup, ResolveError>> {
+        let cached = self.cache.get(&question.qname, &cache_types(question), question.qclass, Instant::now())?;
+        log::debug!("answering {} {:?} from the cache", question.qname, question.qtype);
+        Some(from_cache(question.clone(), cached))
+    }
+
+    /// Refreshes the cache entries which were used shortly before expiring, one at a time on a
+    /// background thread.
+    ///
+    /// The thread is shared by clones of this resolver, and stops once they have all been
+    /// dropped.
+    fn prefetch(&self) {
+        let due = self.due_for_prefetch();
+        if due.is_empty() {
+            return;
+        }
+        let prefetcher = self.prefetcher.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<(Resolver, Question)>();
+            thread::spawn(move || {
+                for (resolver, question) in receiver {
+                    block_on(resolver.refresh(&resolver.transports, question));
+                }
+            });
+            sender
+        });
+        for question in due {
+            // Each refresh takes its own resolver, as clones may be configured differently
+            if prefetcher.send((self.clone(), question)).is_err() {
+                log::debug!("the prefetching thread has stopped");
+            }
+        }
+    }
+
+    /// The questions whose cached answers should be refreshed.
+    fn due_for_prefetch(&self) -> Vec<Question> {
+        self.cache.take_due().into_iter()
+            .map(|(qname, rtype, qclass)| Question { qname, qtype: QType::ByType(rtype), qclass })
+            .collect()
+    }
+
+    /// Looks up `question` again, caching the new answer.
+    async fn refresh<N: Network>(&self, net: &N, question: Question) {
+        log::debug!("prefetching {} {:?}", question.qname, question.qtype);
+        let deadline = Instant::now() + self.config.deadline;
+        let (qname, qclass) = (question.qname.clone(), question.qclass);
+        let rtype = match question.qtype {
+            QType::ByType(rtype) => rtype,
+            _ => unreachable!("only types are prefetched"),
+        };
+        if let Err(e) = self.iterate_uncached(net, question, deadline).await {
+            log::debug!("prefetching failed: {e}");
+        }
+        self.cache.refreshed(&qname, rtype, qclass);
+    }
+
+    /// Caches the RRsets in the answer to `question`, and any denial it contains.
+    ///
+    /// Only the records leading from the question name to the answer are cached, so that a
+    /// server cannot plant records for names it was not asked about.
+    fn cache_reply(&self, question: &Question, reply: &Message) {
+        let (names, aliases) = lookup::alias_chain(question, &reply.answers);
+        self.cache_rrsets(reply.answers.iter().filter(|rr| {
+            aliases.iter().any(|alias| std::ptr::eq(*alias, *rr))
+                || rr.name().is_some_and(|owner| names.iter().any(|name| owner.eq_ignore_case(name)))
+        }), Trust::Answer);
+        let now = Instant::now();
+
+        let rtype = match question.qtype {
+            QType::ByType(rtype) => rtype,
+            _ => return,
+        };
+        let soa = reply.authorities.iter().find(|rr| rr.rtype() == Type::SOA);
+        let lookup = Lookup::from_records(question.clone(), reply.answers.clone(), Vec::new());
+        let name = &lookup.canonical_name;
+        match soa {
+            // The denial applies to the end of any chain of aliases in the answer
+            Some(soa) if reply.rcode() == Rcode::NameError =>
+                self.cache.insert_negative(name, None, question.qclass, soa.clone(), now),
+            Some(soa) if lookup.is_empty() && lookup::next_alias(name, &reply.answers).is_none() =>
+                self.cache.insert_negative(name, Some(rtype), question.qclass, soa.clone(), now),
+            _ => {}
+        }
+    }
+
+    /// Sends `question` to each of the `servers` for `zone` in turn, until one of them gives a
+    /// usable response.
+    ///
+    /// Servers which cannot be reached, respond with an error, or are lame are passed over. If
+    /// none of them are usable, every attempt is returned in the error.
+    async fn query_zone<N: Network>(&self,
+                  net: &N,
+                  question: &Question,
+                  zone: &Name,
+                  servers: &[Nameserver],
+                  deadline: Instant)
+                  -> Result<Message, ResolveError> {
+        if servers.is_empty() {
+            return Err(ResolveError::NoRootHints);
+        }
+        let mut attempts = Vec::new();
+        // Servers whose addresses are known are tried fastest first. The names of the others are
+        // only resolved once all of those have failed.
+        let mut candidates: Vec<(&Nameserver, IpAddr)> = servers.iter()
+            .flat_map(|server| server.addrs.iter().map(move |addr| (server, *addr)))
+            .collect();
+        self.rtt.order(&mut candidates, |(_, addr)| *addr);
+        for (server, addr) in candidates {
+            if let Some(reply) = self.try_server(net, question, zone, server, addr, deadline, &mut attempts).await? {
+                return Ok(reply);
+            }
+        }
+        for server in servers.iter().filter(|server| server.addrs.is_empty()) {
+            let mut addrs = match self.addresses(net, server, zone, deadline).await {
+                Ok(addrs) => addrs,
+                Err(e @ ResolveError::DeadlineExceeded(_)) => return Err(e),
+                Err(error) => {
+                    attempts.push(FailedAttempt { nameserver: server.name.clone(), addr: None, error });
+                    continue;
+                }
+            };
+            self.rtt.order(&mut addrs, |addr| *addr);
+            for addr in addrs {
+                if let Some(reply) = self.try_server(net, question, zone, server, addr, deadline, &mut attempts).await? {
+                    return Ok(reply);
+                }
+            }
+        }
+        Err(ResolveError::AllServersFailed { zone: zone.clone(), attempts })
+    }
+
+    /// Sends `question` to `server` at `addr`, returning its response if it is usable and
+    /// recording the failure in `attempts` if not.
+    #[allow(clippy::too_many_arguments)]
+    async fn try_server<N: Network>(&self,
+                  net: &N,
+                  question: &Question,
+                  zone: &Name,
+                  server: &Nameserver,
+                  addr: IpAddr,
+                  deadline: Instant,
+                  attempts: &mut Vec<FailedAttempt>)
+                  -> Result<Option<Message>, ResolveError> {
+        if let (Some(name), true) = (server.name.as_ref(), server.glue) {
+            self.emit(ResolveEvent::GlueUsed { nameserver: name, addr });
+        }
+        let result = self.query(net, question, addr, deadline).await
+            .and_then(|reply| check_reply(reply, question, zone));
+        match result {
+            Ok(reply) => Ok(Some(reply)),
+            Err(e @ ResolveError::DeadlineExceeded(_)) => Err(e),
+            Err(error) => {
+                log::debug!("passing over {addr} for {zone}: {error}");
+                attempts.push(FailedAttempt { nameserver: server.name.clone(), addr: Some(addr), error });
+                Ok(None)
+            }
+        }
+    }
+
+    /// Resolves the name of `server`, which came without glue.
+    ///
+    /// The future is boxed, as resolving the name may lead back here.
+    fn addresses<'a, N: Network>(&'a self, net: &'a N, server: &'a Nameserver, zone: &'a Name, deadline: Instant)
+                                 -> Pin<Box<dyn Future<Output=Result<Vec<IpAddr>, ResolveError>> + Send + 'a>> {
+        Box::pin(async move {
+            let name = server.name.as_ref().expect("root hints have addresses");
+            // Without glue, a server named inside its own zone can never be reached
+            if name.is_subdomain_of(zone) {
+                return Err(ResolveError::NoNameserverAddress(name.clone()));
+            }
+            let addrs = self.resolve_before(net, name.clone(), deadline).await
+                .map_err(|e| ResolveError::RecursiveLookupFailed(e.into()))?;
+            if addrs.is_empty() {
+                return Err(ResolveError::NoNameserverAddress(name.clone()));
+            }
+            Ok(addrs)
+        })
+    }
+
+    /// Sends `question` to `nameserver`, which is expected to answer it without recursion.
+    async fn query<N: Network>(&self, net: &N, question: &Question, nameserver: IpAddr, deadline: Instant) -> Result<Message, ResolveError> {
+        self.exchange(net, self.query_message(question, false), nameserver, self.config.retries, deadline).await
+    }
+
+    /// Builds the query for `question`, with a random ID and EDNS if it is configured.
+    fn query_message(&self, question: &Question, recursion_desired: bool) -> Message {
+        let mut sent = question.clone();
+        if self.config.randomize_case {
+            sent.qname = randomize_case(&sent.qname);
+        }
+        let mut msg = Message::query(random_u16(), recursion_desired, sent);
+        if let Some(payload_size) = self.config.udp_payload_size {
+            msg.additionals.push(ResourceRecord::OPT {
+                payload_size,
+                extended_rcode: 0,
+                version: 0,
+                dnssec_ok: false,
+                data: Vec::new(),
+            });
+            msg.header.additional_count = 1;
+        }
+        msg
+    }
+
+    /// Sends `msg` to `nameserver`, retransmitting it up to `retries` times.
+    ///
+    /// If the server rejects EDNS with `FORMERR`, the query is sent again without it. Truncated
+    /// responses are retried over TCP.
+    async fn exchange<N: Network>(&self, net: &N, mut msg: Message, nameserver: IpAddr, retries: usize, deadline: Instant) -> Result<Message, ResolveError> {
+        let addr = SocketAddr::new(nameserver, self.config.port);
+        let mut reply = self.query_udp(net, &msg, addr, retries, deadline).await?;
+        if reply.rcode() == Rcode::FormatError && !msg.additionals.is_empty() {
+            log::debug!("{addr} rejected EDNS, retrying without it");
+            msg.additionals.clear();
+            msg.header.additional_count = 0;
+            reply = self.query_udp(net, &msg, addr, retries, deadline).await?;
+        }
+        if reply.truncated() {
+            log::debug!("response from {addr} was truncated, retrying over TCP");
+            reply = self.query_tcp(net, &msg, addr, deadline).await?;
+        }
+        Ok(reply)
+    }
+
+    /// Sends `msg` to `addr` over UDP, retransmitting with a doubling timeout until a response
+    /// arrives, the retries run out or the deadline passes.
+    async fn query_udp<N: Network>(&self, net: &N, msg: &Message, addr: SocketAddr, retries: usize, deadline: Instant) -> Result<Message, ResolveError> {
+        let mut timeout = self.config.timeout;
+        for attempt in 1..=retries + 1 {
+            self.emit(ResolveEvent::QuerySent {
+                server: addr,
+                qname: &msg.questions[0].qname,
+                qtype: msg.questions[0].qtype,
+                tcp: false,
+                attempt,
+            });
+            let sent = Instant::now();
+            match net.udp(msg, addr, timeout.min(self.remaining(deadline)?)).await? {
+                Some(reply) if self.echoes_case(&reply, msg) => {
+                    self.rtt.record(addr.ip(), sent.elapsed());
+                    self.emit(ResolveEvent::ResponseReceived { server: addr, response: &reply });
+                    return Ok(reply);
+                }
+                Some(_) => log::debug!("discarding a response from {addr} which does not echo the query name"),
+                None => {}
+            }
+            timeout *= 2;
+        }
+        self.rtt.penalize(addr.ip(), timeout);
+        Err(ResolveError::Timeout(addr, retries + 1))
+    }
+
+    /// Sends `msg` to `addr` over TCP. TCP handles retransmission, so only the deadline applies.
+    async fn query_tcp<N: Network>(&self, net: &N, msg: &Message, addr: SocketAddr, deadline: Instant) -> Result<Message, ResolveError> {
+        self.emit(ResolveEvent::QuerySent {
+            server: addr,
+            qname: &msg.questions[0].qname,
+            qtype: msg.questions[0].qtype,
+            tcp: true,
+            attempt: 1,
+        });
+        match net.tcp(msg, addr, self.remaining(deadline)?).await? {
+            Some(reply) if self.echoes_case(&reply, msg) => {
+                self.emit(ResolveEvent::ResponseReceived { server: addr, response: &reply });
+                Ok(reply)
+            }
+            Some(_) => {
+                log::debug!("discarding a response from {addr} which does not echo the query name");
+                Err(ResolveError::Timeout(addr, 1))
+            }
+            None => Err(ResolveError::DeadlineExceeded(self.config.deadline)),
+        }
+    }
+
+    /// With `randomize_case`, checks that `reply` repeats the question name with exactly the mix
+    /// of cases sent in `query`. The network has already checked the rest of the question.
+    fn echoes_case(&self, reply: &Message, query: &Message) -> bool {
+        match (reply.questions.first(), query.questions.first()) {
+            (Some(r), Some(q)) if self.config.randomize_case => r.qname == q.qname,
+            _ => true,
+        }
+    }
+
+    /// The time left before `deadline`, which must not have passed.
+    fn remaining(&self, deadline: Instant) -> Result<Duration, ResolveError> {
+        match deadline.saturating_duration_since(Instant::now()) {
+            remaining if remaining.is_zero() => Err(ResolveError::DeadlineExceeded(self.config.deadline)),
+            remaining => Ok(remaining),
+        }
+    }
+
+    fn emit(&self, event: ResolveEvent) {
+        match event {
+            ResolveEvent::ResponseReceived { response, .. } => {
+                log::debug!("{event}");
+                log::trace!("{response}");
+            }
+            _ => log::debug!("{event}"),
+        }
+        if let Some(observer) = self.observer.as_ref() {
+            observer.event(&event);
+        }
+    }
+}
+
+/// A server which was passed over while looking up a name.
+#[derive(Debug)]
+pub struct FailedAttempt {
+    /// The name of the server, or `None` for a root hint.
+    pub nameserver: Option<Name>,
+    /// The address queried, or `None` if no address could be found.
+    pub addr: Option<IpAddr>,
+    /// Why the server could not be used.
+    pub error: ResolveError,
+}
+
+impl fmt::Display for FailedAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.nameserver, &self.addr) {
+            (Some(name), Some(addr)) => write!(f, "{name} ({addr}): {}", self.error),
+            (Some(name), None) => write!(f, "{name}: {}", self.error),
+            (None, Some(addr)) => write!(f, "{addr}: {}", self.error),
+            (None, None) => write!(f, "{}", self.error),
+        }
+    }
+}
+
+fn list_attempts(attempts: &[FailedAttempt]) -> String {
+    attempts.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("query exceeded the maximum lookup depth ({0})")]
+    ExceededMaximumLookupDepth(usize),
+
+    #[error("domain name could not be resolved")]
+    NoSuchDomain,
+
+    #[error("no response from {0} after {1} attempts")]
+    Timeout(SocketAddr, usize),
+
+    #[error("resolution did not complete within {0:?}")]
+    DeadlineExceeded(Duration),
+
+    #[error("no root hints are configured")]
+    NoRootHints,
+
+    #[error("the server responded with {0:?}")]
+    ErrorResponse(Rcode),
+
+    #[error("referral to {0} does not lead closer to the name")]
+    BadReferral(Name),
+
+    #[error("the server is not authoritative for {0}")]
+    LameDelegation(Name),
+
+    #[error("no server for {zone} could be used: {}", list_attempts(attempts))]
+    AllServersFailed { zone: Name, attempts: Vec<FailedAttempt> },
+
+    #[error("the alias {0} leads back to a name already looked up")]
+    AliasLoop(Name),
+
+    #[error("more than {0} aliases were followed")]
+    TooManyAliases(usize),
+
+    #[error("name server {0} has no addresses")]
+    NoNameserverAddress(Name),
+
+    #[error("domain name required a recursive lookup, which failed. {0}")]
+    RecursiveLookupFailed(#[from]Box<ResolveError>),
+
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("host name is not a valid domain name. {0}")]
+    InvalidHost(#[from] NameParseError),
+
+    #[error("deserialization error: {0}")]
+    DeseralizationFailed(#[from] nom::Err<nom::error::Error<Vec<u8>>>),
+}
+
+/// Randomly changes the case of the letters in `name`, for "0x20" encoding.
+fn randomize_case(name: &Name) -> Name {
+    let mut bits = vec![0u8; name.name.len()];
+    random::fill(&mut bits);
+    // Length bytes are at most 63, so are never mistaken for letters
+    let name = name.name.iter().zip(bits)
+        .map(|(&c, bit)| if c.is_ascii_alphabetic() && bit & 1 == 1 { c ^ 0x20 } else { c })
+        .collect();
+    Name { name }
+}
+
+fn is_alias(rr: &ResourceRecord) -> bool {
+    matches!(rr, ResourceRecord::CNAME { .. } | ResourceRecord::DNAME { .. })
+}
+
+/// The types which may answer `question` from the cache: its own, then an alias. Only questions
+/// for a single type are cached.
+fn cache_types(question: &Question) -> Vec<Type> {
+    match question.qtype {
+        QType::ByType(rtype) => vec![rtype, Type::CNAME],
+        _ => Vec::new(),
+    }
+}
+
+/// Builds the outcome of looking up `question` from what the cache holds for it.
+fn from_cache(question: Question, cached: Cached) -> Result<Lookup, ResolveError> {
+    match cached {
+        Cached::Records(records) => Ok(Lookup::from_records(question, records, Vec::new())),
+        Cached::NoData(soa) => Ok(Lookup::from_records(question, Vec::new(), vec![soa])),
+        Cached::NoSuchDomain(_) => Err(ResolveError::NoSuchDomain),
+    }
+}
+
+/// A server for a zone being queried during iteration.
+struct Nameserver {
+    /// The name of the server, or `None` for a root hint.
+    name: Option<Name>,
+    /// The addresses of the server, if they are already known.
+    addrs: Vec<IpAddr>,
+    /// Whether `addrs` came from the additional section of a referral.
+    glue: bool,
+}
+
+/// Checks that `reply` from a server for `zone` is usable: an answer, a denial, or a referral
+/// further down towards the name in `question`.
+fn check_reply(mut reply: Message, question: &Question, zone: &Name) -> Result<Message, ResolveError> {
+    discard_out_of_bailiwick(&mut reply, zone);
+    match reply.rcode() {
+        Rcode::NoError => {}
+        Rcode::NameError if reply.authoritative() => return Ok(reply),
+        rcode => return Err(ResolveError::ErrorResponse(rcode)),
+    }
+    // An answer the server has no authority for, most likely from its own cache
+    if !reply.authoritative() && !reply.answers.is_empty() {
+        return Err(ResolveError::LameDelegation(zone.clone()));
+    }
+    if !is_referral(&reply) {
+        return Ok(reply);
+    }
+    match get_referral(&reply) {
+        Some((child, _)) if child.eq_ignore_case(zone) || !child.is_subdomain_of(zone) || !question.qname.is_subdomain_of(&child) =>
+            Err(ResolveError::BadReferral(child)),
+        Some(_) => Ok(reply),
+        // Neither an answer nor a referral: the server does not know it was delegated the zone
+        None => Err(ResolveError::LameDelegation(zone.clone())),
+    }
+}
+
+/// Determines whether `reply` leaves the question to other servers, rather than answering it.
+fn is_referral(reply: &Message) -> bool {
+    !reply.authoritative() && reply.answers.is_empty()
+}
+
+/// Removes the records which a server for `zone` has no authority to provide.
+fn discard_out_of_bailiwick(msg: &mut Message, zone: &Name) {
+    let in_bailiwick = |rr: &ResourceRecord| match rr.name() {
+        Some(name) if !name.is_subdomain_of(zone) => {
+            log::debug!("discarding out-of-bailiwick record {rr}");
+            false
+        }
+        _ => true,
+    };
+    msg.answers.retain(in_bailiwick);
+    msg.authorities.retain(in_bailiwick);
+    msg.additionals.retain(in_bailiwick);
+}
+
+/// Finds the addresses of `nameserver` in the additional section.
+fn get_glue(msg: &Message, nameserver: &Name) -> Vec<IpAddr> {
+    msg.additionals.iter()
+        .filter_map(|rr| match rr {
+            ResourceRecord::A { name, addr, .. } if name.eq_ignore_case(nameserver) => Some(IpAddr::V4(*addr)),
+            ResourceRecord::AAAA { name, addr, .. } if name.eq_ignore_case(nameserver) => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Finds the zone a response refers to, along with the names of its servers.
+fn get_referral(msg: &Message) -> Option<(Name, Vec<Name>)> {
+    let zone = msg.authorities.iter()
+        .find_map(|rr| match rr {
+            ResourceRecord::NS { name, .. } => Some(name.clone()),
+            _ => None,
+        })?;
+    let nameservers = msg.authorities.iter()
+        .filter_map(|rr| match rr {
+            ResourceRecord::NS { name, ns_name, .. } if name.eq_ignore_case(&zone) => Some(ns_name.clone()),
+            _ => None,
+        })
+        .collect();
+    Some((zone, nameservers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rr::Class;
+    use crate::serial::Serial;
+    use crate::tcp::{read_tcp, write_tcp};
+    use crate::zone::Zone;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::thread;
+
+    fn soa(zone: &str) -> ResourceRecord {
+        ResourceRecord::SOA {
+            name: zone.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(3600),
+            mname: "ns.invalid.".parse().unwrap(),
+            rname: "hostmaster.invalid.".parse().unwrap(),
+            serial: Serial(1),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        }
+    }
+
+    fn ns(name: &str, ns_name: &str) -> ResourceRecord {
+        ResourceRecord::NS {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(3600),
+            ns_name: ns_name.parse().unwrap(),
+        }
+    }
+
+    fn a(name: &str, addr: [u8; 4]) -> ResourceRecord {
+        ResourceRecord::A {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(300),
+            addr: Ipv4Addr::from(addr),
+        }
+    }
+
+    fn cname(name: &str, target: &str) -> ResourceRecord {
+        ResourceRecord::CNAME {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(60),
+            cname: target.parse().unwrap(),
+        }
+    }
+
+    /// Answers queries for `zone` on `socket`, ignoring the first `drop` of them.
+    fn serve(socket: UdpSocket, zone: Zone, drop: usize) {
+        serve_with(socket, drop, move |query| zone.respond(&query));
+    }
+
+    /// Answers queries on `socket` with `respond`, ignoring the first `drop` of them.
+    fn serve_with<F>(socket: UdpSocket, drop: usize, respond: F) where F: Fn(Message) -> Message + Send + 'static {
+        thread::spawn(move || {
+            socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0u8; 512];
+            let mut received = 0;
+            while let Ok((size, from)) = socket.recv_from(&mut buf) {
+                received += 1;
+                if received <= drop {
+                    continue;
+                }
+                let response = respond(Message::decode(&buf[..size]).unwrap());
+                socket.send_to(&response.encode(), from).unwrap();
+            }
+        });
+    }
+
+    /// Binds a socket on each of 127.0.0.1 to 127.0.0.`count`, all on the same port.
+    fn bind_same_port(count: u8) -> Vec<UdpSocket> {
+        // The other addresses may already have the port in use, so keep trying fresh ports
+        (0..10)
+            .find_map(|_| {
+                let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let port = first.local_addr().unwrap().port();
+                let mut sockets = vec![first];
+                for host in 2..=count {
+                    sockets.push(UdpSocket::bind((Ipv4Addr::new(127, 0, 0, host), port)).ok()?);
+                }
+                Some(sockets)
+            })
+            .unwrap()
+    }
+
+    /// Starts servers for the root, `com.` and `example.com.` zones on 127.0.0.1, 127.0.0.2 and
+    /// 127.0.0.3, all on the same port, and returns a configuration pointing at them.
+    pub(super) fn hierarchy(dropped_root_queries: usize) -> ResolverConfig {
+        let mut sockets = bind_same_port(3).into_iter();
+        let (root, com, example) = (sockets.next().unwrap(), sockets.next().unwrap(), sockets.next().unwrap());
+        let port = root.local_addr().unwrap().port();
+        serve(root, Zone::from_records(vec![
+            soa("."),
+            ns(".", "a.root."),
+            a("a.root.", [127, 0, 0, 1]),
+            ns("com.", "ns.com."),
+            a("ns.com.", [127, 0, 0, 2]),
+            ResourceRecord::PTR {
+                name: "1.2.0.192.in-addr.arpa.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(60),
+                ptrname: "www.example.com.".parse().unwrap(),
+            },
+        ]).unwrap(), dropped_root_queries);
+        serve(com, Zone::from_records(vec![
+            soa("com."),
+            ns("com.", "ns.com."),
+            a("ns.com.", [127, 0, 0, 2]),
+            ns("example.com.", "ns1.example.com."),
+            a("ns1.example.com.", [127, 0, 0, 3]),
+            a("cdn.com.", [192, 0, 2, 9]),
+            ResourceRecord::DNAME {
+                name: "legacy.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(60),
+                target: "example.com.".parse().unwrap(),
+            },
+        ]).unwrap(), 0);
+        serve(example, Zone::from_records(vec![
+            soa("example.com."),
+            ns("example.com.", "ns1.example.com."),
+            a("ns1.example.com.", [127, 0, 0, 3]),
+            a("www.example.com.", [192, 0, 2, 1]),
+            cname("alias.example.com.", "www.example.com."),
+            cname("cdn.example.com.", "cdn.com."),
+            cname("loop1.example.com.", "loop2.example.com."),
+            cname("loop2.example.com.", "loop1.example.com."),
+            ResourceRecord::MX {
+                name: "example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(300),
+                preference: 20,
+                exchange: "backup.example.com.".parse().unwrap(),
+            },
+            ResourceRecord::MX {
+                name: "example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(300),
+                preference: 10,
+                exchange: "mail.example.com.".parse().unwrap(),
+            },
+            ResourceRecord::TXT {
+                name: "example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(300),
+                data: vec![b"v=spf1 ".to_vec(), b"-all".to_vec()],
+            },
+            ResourceRecord::SRV {
+                name: "_sip._udp.example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(300),
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: "www.example.com.".parse().unwrap(),
+            },
+        ]).unwrap(), 0);
+        ResolverConfig {
+            root_hints: vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))],
+            port,
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            deadline: Duration::from_secs(5),
+            udp_payload_size: Some(1232),
+            randomize_case: false,
+            ..ResolverConfig::default()
+        }
+    }
+
+    #[test]
+    fn resolves_through_local_hierarchy() {
+        let resolver = Resolver::new(hierarchy(0));
+        assert_eq!(resolver.resolve("www.example.com.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert!(matches!(resolver.resolve("missing.example.com."), Err(ResolveError::NoSuchDomain)));
+        assert_eq!(resolver.reverse_lookup(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))).unwrap(),
+                   vec!["www.example.com.".parse::<Name>().unwrap()]);
+    }
+
+    #[test]
+    fn reverse_lookups_try_the_hosts_file_before_dns() {
+        // As `martin -x` does them
+        let path = std::env::temp_dir().join(format!("martin-reverse-hosts-{}", std::process::id()));
+        std::fs::write(&path, "192.0.2.80 printer.example.com printer\n").unwrap();
+        let resolver = Resolver::new(hierarchy(0)).with_hosts_file(&path);
+        let reverse = |addr: [u8; 4]| resolver.reverse_lookup(IpAddr::V4(Ipv4Addr::from(addr)));
+        assert_eq!(reverse([192, 0, 2, 80]).unwrap(), vec!["printer.example.com.".parse::<Name>().unwrap()]);
+        assert_eq!(reverse([192, 0, 2, 1]).unwrap(), vec!["www.example.com.".parse::<Name>().unwrap()]);
+        assert!(reverse([192, 0, 2, 2]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hierarchies_can_be_simulated_in_memory() {
+        let addr = |last: u8| IpAddr::V4(Ipv4Addr::new(192, 0, 2, last));
+        let transport = MemoryTransport::new()
+            .with_zone(addr(1), Zone::from_records(vec![
+                soa("."),
+                ns(".", "a.root."),
+                a("a.root.", [192, 0, 2, 1]),
+                ns("com.", "ns.com."),
+                a("ns.com.", [192, 0, 2, 2]),
+            ]).unwrap())
+            .with_zone(addr(2), Zone::from_records(vec![
+                soa("com."),
+                ns("example.com.", "ns1.example.com."),
+                a("ns1.example.com.", [192, 0, 2, 3]),
+            ]).unwrap())
+            .with_zone(addr(3), Zone::from_records(vec![
+                soa("example.com."),
+                a("www.example.com.", [192, 0, 2, 80]),
+                cname("alias.example.com.", "www.example.com."),
+            ]).unwrap());
+        let servers = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = servers.clone();
+        let resolver = Resolver::new(ResolverConfig {
+            root_hints: vec![addr(1)],
+            ..ResolverConfig::default()
+        })
+            .with_transport(transport)
+            .with_observer(move |event: &ResolveEvent| {
+                if let ResolveEvent::QuerySent { server, .. } = event {
+                    seen.lock().unwrap().push(server.ip());
+                }
+            });
+
+        assert_eq!(resolver.resolve("www.example.com.").unwrap(), vec![addr(80)]);
+        // Priming, the referrals for the IPv4 address, then the IPv6 one from the cached delegation
+        assert_eq!(*servers.lock().unwrap(), [addr(1), addr(1), addr(2), addr(3), addr(3)]);
+        let lookup = resolver.lookup("alias.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.canonical_name, "www.example.com.".parse().unwrap());
+        assert!(matches!(resolver.resolve("missing.example.com."), Err(ResolveError::NoSuchDomain)));
+    }
+
+    #[test]
+    fn relative_names_are_expanded_through_the_search_list() {
+        let resolver = Resolver::new(ResolverConfig {
+            search: vec!["missing.com.".parse().unwrap(), "example.com.".parse().unwrap()],
+            ..hierarchy(0)
+        });
+        let names = |host| resolver.search_names(host).unwrap().iter().map(Name::to_string).collect::<Vec<_>>();
+        assert_eq!(names("www"), ["www.missing.com.", "www.example.com.", "www."]);
+        assert_eq!(names("www.example"), ["www.example.", "www.example.missing.com.", "www.example.example.com."]);
+        assert_eq!(names("www.example.com."), ["www.example.com."]);
+
+        // The first domain does not exist, so the second is tried
+        assert_eq!(resolver.resolve("www").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        // Names which exist without the records asked for end the search
+        assert!(resolver.lookup_mx("ns1").unwrap().is_empty());
+        assert!(matches!(resolver.resolve("nowhere"), Err(ResolveError::NoSuchDomain)));
+        assert!(matches!(resolver.resolve("www."), Err(ResolveError::NoSuchDomain)));
+    }
+
+    #[test]
+    fn hosts_are_found_before_dns() {
+        let queries = Arc::new(std::sync::Mutex::new(0));
+        let sent = queries.clone();
+        let resolver = Resolver::new(ResolverConfig {
+            search: vec!["example.com.".parse().unwrap()],
+            ..hierarchy(0)
+        });
+        let resolver = resolver
+            .with_hosts(Hosts::parse("192.0.2.80 www.example.com www\n2001:db8::80 www.example.com\n"))
+            .with_observer(move |event: &ResolveEvent| {
+                if let ResolveEvent::QuerySent { .. } = event {
+                    *sent.lock().unwrap() += 1;
+                }
+            });
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 80));
+        let v6: IpAddr = "2001:db8::80".parse().unwrap();
+        assert_eq!(resolver.resolve("www.example.com.").unwrap(), vec![v4, v6]);
+        assert_eq!(resolver.resolve("www").unwrap(), vec![v4, v6]);
+        assert_eq!(resolver.reverse_lookup(v4).unwrap(), vec!["www.example.com.".parse::<Name>().unwrap()]);
+        assert_eq!(resolver.reverse_lookup(v6).unwrap(), vec!["www.example.com.".parse::<Name>().unwrap()]);
+        assert_eq!(*queries.lock().unwrap(), 0);
+
+        // Other types are still looked up in DNS
+        assert!(resolver.lookup_mx("www.example.com.").unwrap().is_empty());
+        assert!(*queries.lock().unwrap() > 0);
+    }
+
+    #[test]
+    fn answers_and_denials_are_cached() {
+        let queries = Arc::new(std::sync::Mutex::new(0));
+        let sent = queries.clone();
+        let resolver = Resolver::new(hierarchy(0)).with_observer(move |event: &ResolveEvent| {
+            if let ResolveEvent::QuerySent { .. } = event {
+                *sent.lock().unwrap() += 1;
+            }
+        });
+        let queries_for = |lookup: &dyn Fn()| {
+            let before = *queries.lock().unwrap();
+            lookup();
+            *queries.lock().unwrap() - before
+        };
+        let www = || resolver.lookup("alias.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert!(queries_for(&|| assert_eq!(www().records().count(), 1)) > 0);
+        assert_eq!(queries_for(&|| assert_eq!(www().records().count(), 1)), 0);
+        assert!(www().ttl().unwrap() <= Ttl::from_secs(60));
+
+        let missing = || resolver.lookup("missing.example.com.", QType::ByType(Type::A), Class::Internet);
+        assert!(queries_for(&|| assert!(matches!(missing(), Err(ResolveError::NoSuchDomain)))) > 0);
+        assert_eq!(queries_for(&|| assert!(matches!(missing(), Err(ResolveError::NoSuchDomain)))), 0);
+
+        let no_mx = || resolver.lookup_mx("www.example.com.").unwrap();
+        assert!(queries_for(&|| assert!(no_mx().is_empty())) > 0);
+        assert_eq!(queries_for(&|| assert!(no_mx().is_empty())), 0);
+
+        let stats = resolver.cache_stats();
+        assert!(stats.hits >= 5);
+        assert!(stats.entries >= 4);
+    }
+
+    #[test]
+    fn iteration_starts_from_cached_delegations() {
+        let servers = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = servers.clone();
+        let resolver = Resolver::new(hierarchy(0)).with_observer(move |event: &ResolveEvent| {
+            if let ResolveEvent::QuerySent { server, qname, .. } = event {
+                seen.lock().unwrap().push((server.ip(), (*qname).clone()));
+            }
+        });
+        resolver.lookup("www.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        let root = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        // The first query primes the root servers
+        assert!(servers.lock().unwrap()[0].1.is_root());
+        assert_eq!(servers.lock().unwrap().iter().filter(|(ip, _)| *ip == root).count(), 2);
+
+        servers.lock().unwrap().clear();
+        resolver.lookup("example.com.", QType::ByType(Type::MX), Class::Internet).unwrap();
+        resolver.lookup("cdn.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(*servers.lock().unwrap(), vec![
+            (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3)), "example.com.".parse().unwrap()),
+            (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), "cdn.com.".parse().unwrap()),
+        ]);
+    }
+
+    #[test]
+    fn lookups_return_typed_records() {
+        let resolver = Resolver::new(hierarchy(0));
+        let lookup = resolver.lookup("alias.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.canonical_name, "www.example.com.".parse().unwrap());
+        assert_eq!(lookup.records().cloned().collect::<Vec<_>>(), vec![a("www.example.com.", [192, 0, 2, 1])]);
+        assert_eq!(lookup.ttl(), Some(Ttl::from_secs(60)));
+
+        let mx = resolver.lookup_mx("example.com.").unwrap();
+        assert_eq!(mx, vec![(10, "mail.example.com.".parse().unwrap()), (20, "backup.example.com.".parse().unwrap())]);
+        assert_eq!(resolver.lookup_txt("example.com.").unwrap(), vec!["v=spf1 -all"]);
+        assert_eq!(resolver.lookup_ns("example.com.").unwrap(), vec!["ns1.example.com.".parse().unwrap()]);
+        assert!(resolver.lookup_soa("example.com.").unwrap().is_some());
+        assert!(matches!(resolver.lookup_srv("_sip._udp.example.com.").unwrap().as_slice(),
+                         [ResourceRecord::SRV { port: 5060, .. }]));
+
+        let empty = resolver.lookup("www.example.com.", QType::ByType(Type::MX), Class::Internet).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.authorities[0].rtype(), Type::SOA);
+        assert!(resolver.lookup_aaaa("www.example.com.").unwrap().is_empty());
+    }
+
+    #[test]
+    fn aliases_are_followed_across_zones() {
+        let resolver = Resolver::new(hierarchy(0));
+        assert_eq!(resolver.resolve("cdn.example.com.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 9))]);
+
+        let lookup = resolver.lookup("www.legacy.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.canonical_name, "www.example.com.".parse().unwrap());
+        assert_eq!(lookup.aliases().count(), 1);
+        assert_eq!(lookup.records().cloned().collect::<Vec<_>>(), vec![a("www.example.com.", [192, 0, 2, 1])]);
+
+        let alias = resolver.lookup("cdn.example.com.", QType::ByType(Type::CNAME), Class::Internet).unwrap();
+        assert_eq!(alias.records().cloned().collect::<Vec<_>>(), vec![cname("cdn.example.com.", "cdn.com.")]);
+
+        assert!(matches!(resolver.resolve("loop1.example.com."), Err(ResolveError::AliasLoop(_))));
+    }
+
+    #[test]
+    fn observer_sees_each_step() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let resolver = Resolver::new(hierarchy(0)).with_observer(move |event: &ResolveEvent| {
+            let summary = match event {
+                ResolveEvent::QuerySent { server, .. } => format!("query {}", server.ip()),
+                ResolveEvent::ResponseReceived { server, .. } => format!("response {}", server.ip()),
+                ResolveEvent::ReferralFollowed { zone, nameserver } => format!("referral {zone} {nameserver}"),
+                ResolveEvent::GlueUsed { nameserver, addr } => format!("glue {nameserver} {addr}"),
+            };
+            seen.lock().unwrap().push(summary);
+        });
+        resolver.lookup("www.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(*events.lock().unwrap(), vec![
+            // Priming
+            "query 127.0.0.1",
+            "response 127.0.0.1",
+            "query 127.0.0.1",
+            "response 127.0.0.1",
+            "referral com. ns.com.",
+            "glue ns.com. 127.0.0.2",
+            "query 127.0.0.2",
+            "response 127.0.0.2",
+            "referral example.com. ns1.example.com.",
+            "glue ns1.example.com. 127.0.0.3",
+            "query 127.0.0.3",
+            "response 127.0.0.3",
+        ]);
+    }
+
+    #[test]
+    fn lost_queries_are_retransmitted() {
+        let resolver = Resolver::new(hierarchy(2));
+        assert_eq!(resolver.resolve("www.example.com.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+    }
+
+    #[test]
+    fn silent_servers_time_out() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+        let mut config = ResolverConfig {
+            root_hints: vec![addr.ip()],
+            port: addr.port(),
+            timeout: Duration::from_millis(50),
+            retries: 1,
+            deadline: Duration::from_secs(5),
+            udp_payload_size: Some(1232),
+            randomize_case: false,
+            ..ResolverConfig::default()
+        };
+        let result = Resolver::new(config.clone()).resolve("www.example.com.");
+        match result {
+            Err(ResolveError::AllServersFailed { attempts, .. }) => {
+                assert_eq!(attempts.len(), 1);
+                assert!(matches!(attempts[0].error, ResolveError::Timeout(a, 2) if a == addr));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        config.retries = 10;
+        config.deadline = Duration::from_millis(300);
+        let start = Instant::now();
+        let result = Resolver::new(config).resolve("www.example.com.");
+        assert!(matches!(result, Err(ResolveError::DeadlineExceeded(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// A root server holding `www.example.` which answers with `respond` over UDP and normally
+    /// over TCP.
+    fn root_server<F>(respond: F) -> ResolverConfig
+        where F: Fn(&Zone, Message) -> Message + Send + 'static
+    {
+        let zone = Zone::from_records(vec![soa("."), ns(".", "a.root."), a("www.example.", [192, 0, 2, 7])]).unwrap();
+        let (udp, tcp) = (0..10)
+            .find_map(|_| {
+                let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let tcp = std::net::TcpListener::bind(udp.local_addr().unwrap()).ok()?;
+                Some((udp, tcp))
+            })
+            .unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp_zone = zone.clone();
+        thread::spawn(move || {
+            udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut buf = [0u8; 512];
+            while let Ok((size, from)) = udp.recv_from(&mut buf) {
+                let response = respond(&zone, Message::decode(&buf[..size]).unwrap());
+                udp.send_to(&response.encode(), from).unwrap();
+            }
+        });
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                let mut stream = stream.unwrap();
+                let request = Message::decode(&read_tcp(&mut stream).unwrap()).unwrap();
+                write_tcp(&mut stream, &tcp_zone.respond(&request)).unwrap();
+            }
+        });
+        ResolverConfig {
+            root_hints: vec![addr.ip()],
+            port: addr.port(),
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            deadline: Duration::from_secs(5),
+            udp_payload_size: Some(1400),
+            randomize_case: false,
+            ..ResolverConfig::default()
+        }
+    }
+
+    #[test]
+    fn referrals_must_lead_towards_the_name() {
+        let resolver = Resolver::new(root_server(|_, query| {
+            let mut response = Message::response(query, false);
+            response.authorities.push(ns("org.", "ns.org."));
+            response.additionals.push(a("ns.org.", [127, 0, 0, 1]));
+            response.header.ns_count = 1;
+            response.header.additional_count = 1;
+            response
+        }));
+        let result = resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet);
+        assert!(matches!(result, Err(ResolveError::AllServersFailed { attempts, .. })
+                         if matches!(&attempts[0].error, ResolveError::BadReferral(zone) if *zone == "org.".parse().unwrap())));
+    }
+
+    #[test]
+    fn failing_nameservers_are_passed_over() {
+        let mut sockets = bind_same_port(6).into_iter();
+        let root = sockets.next().unwrap();
+        let port = root.local_addr().unwrap().port();
+        serve(root, Zone::from_records(vec![
+            soa("."),
+            ns(".", "a.root."),
+            ns("example.", "silent.ns."),
+            ns("example.", "refusing.ns."),
+            ns("example.", "lame.ns."),
+            ns("example.", "good.ns."),
+            ns("broken.", "silent.ns."),
+            ns("broken.", "refusing.ns."),
+            ns("broken.", "lame.ns."),
+            ns("broken.", "caching.ns."),
+            ns("broken.", "missing.ns.example."),
+            a("silent.ns.", [127, 0, 0, 2]),
+            a("refusing.ns.", [127, 0, 0, 3]),
+            a("lame.ns.", [127, 0, 0, 4]),
+            a("good.ns.", [127, 0, 0, 5]),
+            a("caching.ns.", [127, 0, 0, 6]),
+        ]).unwrap(), 0);
+        let _silent = sockets.next().unwrap();
+        serve(sockets.next().unwrap(), Zone::from_records(vec![soa("org.")]).unwrap(), 0);
+        serve_with(sockets.next().unwrap(), 0, |query| Message::response(query, false));
+        serve(sockets.next().unwrap(), Zone::from_records(vec![
+            soa("example."),
+            a("www.example.", [192, 0, 2, 7]),
+        ]).unwrap(), 0);
+        // Answers without authority, as a recursive resolver would
+        serve_with(sockets.next().unwrap(), 0, |query| {
+            let mut response = Message::response(query, true);
+            response.answers.push(a("www.broken.", [203, 0, 113, 1]));
+            response.header.answer_count = 1;
+            response
+        });
+        let resolver = Resolver::new(ResolverConfig {
+            root_hints: vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))],
+            port,
+            timeout: Duration::from_millis(50),
+            retries: 0,
+            deadline: Duration::from_secs(5),
+            udp_payload_size: Some(1232),
+            randomize_case: false,
+            ..ResolverConfig::default()
+        });
+
+        assert_eq!(resolver.resolve("www.example.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))]);
+
+        let attempts = match resolver.lookup("www.broken.", QType::ByType(Type::A), Class::Internet) {
+            Err(ResolveError::AllServersFailed { zone, attempts }) if zone == "broken.".parse().unwrap() => attempts,
+            other => panic!("unexpected result {other:?}"),
+        };
+        assert_eq!(attempts.len(), 5);
+        let error = |ns: &str| &attempts.iter().find(|a| a.nameserver == Some(ns.parse().unwrap())).unwrap().error;
+        assert!(matches!(error("silent.ns."), ResolveError::Timeout(..)));
+        assert!(matches!(error("refusing.ns."), ResolveError::ErrorResponse(Rcode::Refused)));
+        assert!(matches!(error("lame.ns."), ResolveError::LameDelegation(zone) if *zone == "broken.".parse().unwrap()));
+        assert!(matches!(error("caching.ns."), ResolveError::LameDelegation(zone) if *zone == "broken.".parse().unwrap()));
+        // Servers without glue are only resolved once the others have failed
+        assert_eq!(attempts[4].nameserver, Some("missing.ns.example.".parse().unwrap()));
+        assert!(matches!(attempts[4].error, ResolveError::RecursiveLookupFailed(_)));
+        assert_eq!(attempts[4].addr, None);
+    }
+
+    #[test]
+    fn fastest_nameservers_are_preferred() {
+        let mut sockets = bind_same_port(4).into_iter();
+        let root = sockets.next().unwrap();
+        let port = root.local_addr().unwrap().port();
+        let records = vec![
+            soa("."),
+            ns(".", "a.root."),
+            ns("example.", "silent.ns."),
+            ns("example.", "slow.ns."),
+            ns("example.", "fast.ns."),
+            a("silent.ns.", [127, 0, 0, 2]),
+            a("slow.ns.", [127, 0, 0, 3]),
+            a("fast.ns.", [127, 0, 0, 4]),
+        ];
+        let mut example = vec![soa("example.")];
+        for i in 0..10 {
+            example.push(a(&format!("host{i}.example."), [192, 0, 2, i]));
+        }
+        serve(root, Zone::from_records(records).unwrap(), 0);
+        let _silent = sockets.next().unwrap();
+        let slow_zone = Zone::from_records(example.clone()).unwrap();
+        serve_with(sockets.next().unwrap(), 0, move |query| {
+            thread::sleep(Duration::from_millis(100));
+            slow_zone.respond(&query)
+        });
+        serve(sockets.next().unwrap(), Zone::from_records(example).unwrap(), 0);
+
+        let answered = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = answered.clone();
+        let resolver = Resolver::new(ResolverConfig {
+            root_hints: vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))],
+            port,
+            timeout: Duration::from_millis(300),
+            retries: 0,
+            deadline: Duration::from_secs(5),
+            udp_payload_size: Some(1232),
+            randomize_case: false,
+            ..ResolverConfig::default()
+        }).with_observer(move |event: &ResolveEvent| {
+            if let ResolveEvent::ResponseReceived { server, .. } = event {
+                seen.lock().unwrap().push(server.ip());
+            }
+        });
+        for i in 0..10 {
+            let lookup = resolver.lookup(&format!("host{i}.example."), QType::ByType(Type::A), Class::Internet).unwrap();
+            assert_eq!(lookup.records().count(), 1);
+        }
+
+        let slow = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3));
+        let fast = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 4));
+        let answered = answered.lock().unwrap();
+        let from = |addr| answered.iter().filter(|a| **a == addr).count();
+        // Each server is tried at most once before the fast one is settled on
+        assert!(from(slow) <= 1);
+        assert!(from(fast) >= 9);
+        assert!(resolver.smoothed_rtt(fast).unwrap() < resolver.smoothed_rtt(slow).unwrap_or(Duration::MAX));
+        assert!(resolver.smoothed_rtt(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))).unwrap_or(Duration::MAX) >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn out_of_bailiwick_records_are_discarded() {
+        let query = Message::query(1, false, Question::new("www.example.com.", QType::ByType(Type::A)).unwrap());
+        let mut referral = Message::response(query, false);
+        referral.answers.push(a("www.example.net.", [203, 0, 113, 1]));
+        referral.authorities.push(ns("example.com.", "ns1.example.com."));
+        referral.authorities.push(ns("example.net.", "ns1.example.net."));
+        referral.additionals.push(a("www.example.com.", [203, 0, 113, 2]));
+        referral.additionals.push(a("ns1.example.net.", [203, 0, 113, 3]));
+        referral.additionals.push(a("ns1.example.com.", [192, 0, 2, 53]));
+
+        discard_out_of_bailiwick(&mut referral, &"com.".parse().unwrap());
+        assert!(referral.answers.is_empty());
+        assert_eq!(referral.authorities, vec![ns("example.com.", "ns1.example.com.")]);
+        assert_eq!(referral.additionals.len(), 2);
+
+        let (zone, nameservers) = get_referral(&referral).unwrap();
+        assert_eq!(zone, "example.com.".parse().unwrap());
+        assert_eq!(get_glue(&referral, &nameservers[0]), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 53))]);
+    }
+
+    #[test]
+    fn stale_answers_are_served_when_servers_fail() {
+        let down = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let refusing = down.clone();
+        let mut config = root_server(move |zone, query| {
+            let mut response = zone.respond(&query);
+            if refusing.load(std::sync::atomic::Ordering::SeqCst) {
+                response = Message::response(query, false);
+                response.header.rcode = Rcode::Refused;
+            }
+            response
+        });
+        config.max_cache_ttl = Ttl::from_secs(1);
+        let resolver = Resolver::new(config.clone());
+        resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        down.store(true, std::sync::atomic::Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(1100));
+        assert!(resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).is_err());
+
+        config.max_stale = Some(Duration::from_secs(60));
+        down.store(false, std::sync::atomic::Ordering::SeqCst);
+        let resolver = Resolver::new(config);
+        resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        down.store(true, std::sync::atomic::Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(1100));
+        let stale = resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(stale.records().cloned().collect::<Vec<_>>(), vec![a("www.example.", [192, 0, 2, 7]).with_class_and_ttl(Class::Internet, Ttl::from_secs(30))]);
+        assert_eq!(resolver.cache_stats().stale_hits, 1);
+    }
+
+    #[test]
+    fn unrelated_answers_are_not_cached() {
+        let queries = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = queries.clone();
+        let resolver = Resolver::new(root_server(move |zone, query| {
+            counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut response = zone.respond(&query);
+            if query.questions[0].qname == "www.example.".parse().unwrap() {
+                response.answers.push(a("bank.example.", [203, 0, 113, 66]));
+                response.header.answer_count += 1;
+            }
+            response
+        }));
+        resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        let before = queries.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(resolver.lookup("bank.example.", QType::ByType(Type::A), Class::Internet).is_err());
+        assert!(queries.load(std::sync::atomic::Ordering::SeqCst) > before);
+    }
+
+    #[test]
+    fn popular_answers_are_prefetched() {
+        let queries = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counted = queries.clone();
+        let mut config = root_server(move |zone, query| {
+            if query.questions[0].qtype == QType::ByType(Type::A) {
+                counted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+            zone.respond(&query)
+        });
+        config.max_cache_ttl = Ttl::from_secs(2);
+        config.prefetch = true;
+        let resolver = Resolver::new(config);
+        let lookup = || resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        lookup();
+        thread::sleep(Duration::from_millis(1850));
+        // Answered from the cache, but refreshed in the background
+        lookup();
+        thread::sleep(Duration::from_millis(300));
+        lookup();
+        assert_eq!(queries.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(resolver.cache_stats().prefetches >= 1);
+        assert_eq!(resolver.cache_stats().misses, 1);
+    }
+
+    #[test]
+    fn minimised_names_are_sent_to_each_zone() {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = sent.clone();
+        let mut config = hierarchy(0);
+        config.qname_minimisation = true;
+        let resolver = Resolver::new(config).with_observer(move |event: &ResolveEvent| {
+            if let ResolveEvent::QuerySent { server, qname, qtype, .. } = event {
+                seen.lock().unwrap().push((server.ip().to_string(), qname.to_string(), *qtype));
+            }
+        });
+        let lookup = resolver.lookup("_sip._udp.example.com.", QType::ByType(Type::SRV), Class::Internet).unwrap();
+        assert_eq!(lookup.records().count(), 1);
+        let minimised = QType::ByType(Type::A);
+        assert_eq!(sent.lock().unwrap()[1..], [
+            ("127.0.0.1".to_string(), "com.".to_string(), minimised),
+            ("127.0.0.2".to_string(), "example.com.".to_string(), minimised),
+            // An empty non-terminal
+            ("127.0.0.3".to_string(), "_udp.example.com.".to_string(), minimised),
+            ("127.0.0.3".to_string(), "_sip._udp.example.com.".to_string(), QType::ByType(Type::SRV)),
+        ]);
+    }
+
+    #[test]
+    fn minimisation_falls_back_to_full_names() {
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        let mut config = root_server(move |zone, query| {
+            let qname = query.questions[0].qname.clone();
+            seen.lock().unwrap().push(qname.to_string());
+            if qname == "example.".parse().unwrap() {
+                let mut response = Message::response(query, false);
+                response.header.authoritative = true;
+                response.header.rcode = Rcode::NameError;
+                return response;
+            }
+            zone.respond(&query)
+        });
+        config.qname_minimisation = true;
+        let lookup = Resolver::new(config).lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.records().count(), 1);
+        assert_eq!(asked.lock().unwrap()[1..], ["example.", "www.example."]);
+    }
+
+    #[test]
+    fn stub_resolvers_ask_recursive_servers_in_order() {
+        let mut sockets = bind_same_port(3).into_iter();
+        let port = sockets.next().unwrap().local_addr().unwrap().port();
+        // 127.0.0.1 is left silent
+        serve_with(sockets.next().unwrap(), 0, |query| {
+            let mut response = Message::response(query, true);
+            response.header.rcode = Rcode::ServerFailure;
+            response
+        });
+        let zone = Zone::from_records(vec![soa("example.com."), a("www.example.com.", [192, 0, 2, 1])]).unwrap();
+        serve_with(sockets.next().unwrap(), 0, move |query| {
+            let recursive = query.recursion_desired();
+            let mut response = zone.respond(&query);
+            response.header.recursion_available = true;
+            if !recursive {
+                response.header.rcode = Rcode::Refused;
+            }
+            response
+        });
+
+        let conf = ResolvConf::parse("nameserver 127.0.0.1\nnameserver 127.0.0.2\nnameserver 127.0.0.3\noptions attempts:1\n");
+        let mut config = ResolverConfig::from(&conf);
+        config.port = port;
+        config.timeout = Duration::from_millis(100);
+        let servers = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = servers.clone();
+        let observer = move |event: &ResolveEvent| {
+            if let ResolveEvent::QuerySent { server, .. } = event {
+                seen.lock().unwrap().push(server.ip().to_string());
+            }
+        };
+        let resolver = Resolver::new(config.clone()).with_observer(observer.clone());
+        let lookup = resolver.lookup("www.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.records().count(), 1);
+        assert_eq!(*servers.lock().unwrap(), ["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+        assert!(matches!(resolver.lookup("missing.example.com.", QType::ByType(Type::A), Class::Internet),
+                         Err(ResolveError::NoSuchDomain)));
+
+        config.nameservers = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3)), IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
+        config.rotate = true;
+        config.max_cache_bytes = 0;
+        let resolver = Resolver::new(config).with_observer(observer);
+        servers.lock().unwrap().clear();
+        for _ in 0..2 {
+            resolver.lookup("www.example.com.", QType::ByType(Type::A), Class::Internet).unwrap();
+        }
+        assert_eq!(*servers.lock().unwrap(), ["127.0.0.3", "127.0.0.2", "127.0.0.3"]);
+    }
+
+    #[test]
+    fn truncated_responses_are_retried_over_tcp() {
+        let resolver = Resolver::new(root_server(|_, query| {
+            let mut response = Message::response(query, false);
+            response.header.truncated = true;
+            response
+        }));
+        assert_eq!(resolver.resolve("www.example.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))]);
+    }
+
+    #[test]
+    fn edns_is_dropped_after_formerr() {
+        let sizes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = sizes.clone();
+        let resolver = Resolver::new(root_server(move |zone, query| {
+            let edns = query.additionals.iter().find_map(|rr| match rr {
+                ResourceRecord::OPT { payload_size, .. } => Some(*payload_size),
+                _ => None,
+            });
+            seen.lock().unwrap().push(edns);
+            match edns {
+                Some(_) => {
+                    let mut response = Message::response(query, false);
+                    response.header.rcode = Rcode::FormatError;
+                    response
+                }
+                None => zone.respond(&query),
+            }
+        }));
+        resolver.prime().unwrap();
+        sizes.lock().unwrap().clear();
+        let lookup = resolver.lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.records().count(), 1);
+        assert_eq!(*sizes.lock().unwrap(), vec![Some(1400), None]);
+    }
+
+    #[test]
+    fn forged_responses_are_ignored() {
+        let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = ids.clone();
+        let mut config = root_server(move |zone, query| {
+            seen.lock().unwrap().push(query.id());
+            zone.respond(&query)
+        });
+        // Every lookup must reach the server
+        config.max_cache_bytes = 0;
+        let resolver = Resolver::new(config);
+        let addr = SocketAddr::new(resolver.config().root_hints[0], resolver.config().port);
+        for _ in 0..4 {
+            resolver.resolve("www.example.").unwrap();
+        }
+        let first = ids.lock().unwrap()[0];
+        assert!(ids.lock().unwrap().iter().any(|id| *id != first));
+
+        let forger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ResolverConfig {
+            root_hints: vec![forger.local_addr().unwrap().ip()],
+            port: forger.local_addr().unwrap().port(),
+            ..resolver.config().clone()
+        };
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (size, from) = forger.recv_from(&mut buf).unwrap();
+            let query = Message::decode(&buf[..size]).unwrap();
+            let mut wrong_id = query.clone();
+            wrong_id.header.id = query.id().wrapping_add(1);
+            let mut wrong_name = query.clone();
+            wrong_name.questions[0].qname = "www.example.net.".parse().unwrap();
+            let mut wrong_type = query.clone();
+            wrong_type.questions[0].qtype = QType::ByType(Type::MX);
+            let mut not_response = query.clone();
+            not_response.header.qr = false;
+            for forged in [wrong_id, wrong_name, wrong_type, not_response] {
+                let mut response = Message::response(forged.clone(), false);
+                response.header.qr = forged.header.qr;
+                response.answers.push(a("www.example.", [203, 0, 113, 66]));
+                response.header.answer_count = 1;
+                forger.send_to(&response.encode(), from).unwrap();
+            }
+            // Relay the genuine answer last
+            let genuine = UdpSocket::bind("127.0.0.1:0").unwrap();
+            genuine.send_to(&query.encode(), addr).unwrap();
+            let size = genuine.recv(&mut buf).unwrap();
+            forger.send_to(&buf[..size], from).unwrap();
+        });
+        let lookup = Resolver::new(config).lookup("www.example.", QType::ByType(Type::A), Class::Internet).unwrap();
+        assert_eq!(lookup.records().cloned().collect::<Vec<_>>(), vec![a("www.example.", [192, 0, 2, 7])]);
+    }
+
+    #[test]
+    fn randomized_case_must_be_echoed() {
+        let mut config = hierarchy(0);
+        config.randomize_case = true;
+        assert_eq!(Resolver::new(config).resolve("www.example.com.").unwrap(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+
+        let mut config = root_server(|zone, query| {
+            let mut response = zone.respond(&query);
+            response.questions[0].qname = response.questions[0].qname.to_lowercase();
+            response
+        });
+        config.randomize_case = true;
+        config.retries = 0;
+        let result = Resolver::new(config).resolve("abcdefghijklmnopqrstuvwxyz.example.");
+        assert!(matches!(result, Err(ResolveError::AllServersFailed { attempts, .. })
+                         if matches!(attempts[0].error, ResolveError::Timeout(..))));
+    }
+
+    #[test]
+    fn default_root_hints() {
+        let hints = ResolverConfig::default().root_hints;
+        assert_eq!(hints.len(), 26);
+        assert_eq!(hints[0], "198.41.0.4".parse::<IpAddr>().unwrap());
+        assert!(hints[..13].iter().all(IpAddr::is_ipv4));
+        assert!(hints[13..].iter().all(IpAddr::is_ipv6));
+    }
+}
@@ -0,0 +1,72 @@
+use super::transport::{TcpTransport, Transport, UdpTransport};
+use super::ResolveError;
+use crate::header::Rcode;
+use crate::message::Message;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Large enough for any response sent over UDP.
+pub(crate) const MAX_UDP_SIZE: usize = 65_535;
+
+/// How a `Resolver` exchanges messages with servers.
+///
+/// The resolution logic is written once, as `async` code over this trait, and shared by the
+/// blocking and asynchronous resolvers. Timing out is left to each network, so that dropping a
+/// lookup part way through leaves nothing behind.
+pub(crate) trait Network: Send + Sync {
+    /// Sends `msg` to `server` over UDP, and waits up to `timeout` for the response to it.
+    /// Returns `None` if none arrives in time.
+    fn udp(&self, msg: &Message, server: SocketAddr, timeout: Duration)
+           -> impl Future<Output=Result<Option<Message>, ResolveError>> + Send;
+
+    /// Sends `msg` to `server` over TCP, and waits up to `timeout` for the response to it.
+    /// Returns `None` if none arrives in time.
+    fn tcp(&self, msg: &Message, server: SocketAddr, timeout: Duration)
+           -> impl Future<Output=Result<Option<Message>, ResolveError>> + Send;
+}
+
+/// The network of the blocking `Resolver`: its transports, which do all their work as soon as
+/// they are polled.
+#[derive(Clone)]
+pub(crate) struct Transports {
+    pub udp: Arc<dyn Transport>,
+    pub tcp: Arc<dyn Transport>,
+}
+
+impl Default for Transports {
+    fn default() -> Transports {
+        Transports { udp: Arc::new(UdpTransport), tcp: Arc::new(TcpTransport) }
+    }
+}
+
+impl fmt::Debug for Transports {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transports").finish_non_exhaustive()
+    }
+}
+
+impl Network for Transports {
+    async fn udp(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        self.udp.exchange(msg, server, timeout)
+    }
+
+    async fn tcp(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        self.tcp.exchange(msg, server, timeout)
+    }
+}
+
+/// Checks that `reply` answers `query`: a response with the same ID, repeating the question in
+/// any case. Servers rejecting a query as malformed may leave out the question.
+pub(crate) fn answers(reply: &Message, query: &Message) -> bool {
+    if !reply.is_response() || reply.id() != query.id() || reply.opcode() != query.opcode() {
+        return false;
+    }
+    match (reply.questions.as_slice(), query.questions.as_slice()) {
+        ([r], [q]) => r.qname.eq_ignore_case(&q.qname) && r.qtype == q.qtype && r.qclass == q.qclass,
+        ([], _) => reply.rcode() == Rcode::FormatError,
+        _ => false,
+    }
+}
@@ -0,0 +1,164 @@
+use crate::names::Name;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::Duration;
+
+/// Where the system resolver is configured on Unix systems.
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// The most name servers used, as with glibc's `MAXNS`.
+const MAX_NAMESERVERS: usize = 3;
+
+/// The settings of the system resolver, as read from `resolv.conf(5)`.
+///
+/// Parsing is as forgiving as glibc's: lines which cannot be understood are ignored, and values
+/// out of range are clamped.
+///
+/// ```
+/// # use martin::resolve::ResolvConf;
+/// let conf = ResolvConf::parse("nameserver 192.0.2.53\nsearch example.com\noptions ndots:2 rotate\n");
+/// assert_eq!(conf.nameservers, vec!["192.0.2.53".parse::<std::net::IpAddr>().unwrap()]);
+/// assert_eq!(conf.search, vec!["example.com.".parse().unwrap()]);
+/// assert_eq!(conf.ndots, 2);
+/// assert!(conf.rotate);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvConf {
+    /// The recursive resolvers to ask, in order. Defaults to the local host.
+    pub nameservers: Vec<IpAddr>,
+    /// The domains tried in turn for relative names, from `search` or `domain`, whichever
+    /// comes last.
+    pub search: Vec<Name>,
+    /// How many dots a name needs before it is tried as an absolute name first.
+    pub ndots: usize,
+    /// How long to wait for each server to respond.
+    pub timeout: Duration,
+    /// How many times to go through the list of servers.
+    pub attempts: usize,
+    /// Whether to spread queries over the servers, rather than always starting with the first.
+    pub rotate: bool,
+}
+
+impl Default for ResolvConf {
+    fn default() -> ResolvConf {
+        ResolvConf {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+        }
+    }
+}
+
+impl ResolvConf {
+    /// Reads the system configuration from `/etc/resolv.conf`.
+    pub fn system() -> io::Result<ResolvConf> {
+        ResolvConf::load(RESOLV_CONF_PATH)
+    }
+
+    /// Reads the configuration from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ResolvConf> {
+        Ok(ResolvConf::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses the contents of a `resolv.conf` file.
+    pub fn parse(contents: &str) -> ResolvConf {
+        let mut conf = ResolvConf::default();
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // Scoped IPv6 addresses ("fe80::1%eth0") lose their scope
+                    let addr = words.next().and_then(|addr| addr.split('%').next()?.parse().ok());
+                    if let Some(addr) = addr {
+                        if conf.nameservers.len() < MAX_NAMESERVERS {
+                            conf.nameservers.push(addr);
+                        }
+                    }
+                }
+                Some("domain") => conf.search = words.next().and_then(parse_domain).into_iter().collect(),
+                Some("search") => conf.search = words.filter_map(parse_domain).collect(),
+                Some("options") => {
+                    for option in words {
+                        conf.set_option(option);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if conf.nameservers.is_empty() {
+            conf.nameservers.push(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+        conf
+    }
+
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = match option.split_once(':') {
+            Some((name, value)) => (name, value.parse::<usize>().ok()),
+            None => (option, None),
+        };
+        match (name, value) {
+            ("ndots", Some(ndots)) => self.ndots = ndots.min(15),
+            ("timeout", Some(timeout)) => self.timeout = Duration::from_secs(timeout.clamp(1, 30) as u64),
+            ("attempts", Some(attempts)) => self.attempts = attempts.clamp(1, 5),
+            ("rotate", None) => self.rotate = true,
+            _ => {}
+        }
+    }
+}
+
+/// Parses a search domain, which is usually written without its trailing dot.
+fn parse_domain(domain: &str) -> Option<Name> {
+    if domain.ends_with('.') {
+        domain.parse().ok()
+    } else {
+        format!("{domain}.").parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_setting() {
+        let conf = ResolvConf::parse("\
+# Generated by NetworkManager
+domain corp.example
+search eng.example.com example.com.
+nameserver 192.0.2.1
+nameserver fe80::1%eth0
+; a comment
+nameserver not-an-address
+nameserver 192.0.2.2
+nameserver 192.0.2.3
+options timeout:2 attempts:9 ndots:20 rotate edns0
+");
+        assert_eq!(conf, ResolvConf {
+            nameservers: vec![
+                "192.0.2.1".parse().unwrap(),
+                "fe80::1".parse().unwrap(),
+                "192.0.2.2".parse().unwrap(),
+            ],
+            search: vec!["eng.example.com.".parse().unwrap(), "example.com.".parse().unwrap()],
+            ndots: 15,
+            timeout: Duration::from_secs(2),
+            attempts: 5,
+            rotate: true,
+        });
+    }
+
+    #[test]
+    fn empty_files_use_the_local_host() {
+        let conf = ResolvConf::parse("");
+        assert_eq!(conf.nameservers, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        assert_eq!(conf.ndots, 1);
+        assert_eq!(conf.attempts, 2);
+        assert_eq!(ResolvConf::parse("search a.example\ndomain b.example\n").search,
+                   vec!["b.example.".parse().unwrap()]);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::random::random_u16;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The weight given to each new measurement when updating a smoothed RTT.
+const MEASUREMENT_WEIGHT: f64 = 0.3;
+
+/// The factor applied to the smoothed RTT of each server passed over in favour of a faster one.
+/// Slow servers gradually become attractive again, so they are probed now and then.
+const DECAY: f64 = 0.98;
+
+/// The most a server can be penalised for timing out.
+const MAX_RTT: Duration = Duration::from_secs(10);
+
+/// Unknown servers are given a random estimate below this, so each is tried soon, in a random
+/// order.
+const MAX_UNKNOWN_RTT_MS: u16 = 32;
+
+/// How many servers' RTTs are remembered. The least recently measured are forgotten first.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Smoothed round-trip times of the servers a `Resolver` has queried, in the manner of BIND.
+#[derive(Debug)]
+pub(crate) struct RttTable {
+    servers: Mutex<Servers>,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct Servers {
+    /// The smoothed RTT of each server, and its position in `recency`.
+    rtts: HashMap<IpAddr, (Duration, u64)>,
+    /// Servers by when they were last measured, oldest first.
+    recency: BTreeMap<u64, IpAddr>,
+    clock: u64,
+}
+
+impl Default for RttTable {
+    fn default() -> RttTable {
+        RttTable::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl RttTable {
+    /// Creates a table remembering at most `capacity` servers.
+    pub fn with_capacity(capacity: usize) -> RttTable {
+        RttTable { servers: Mutex::default(), capacity }
+    }
+
+    /// The smoothed RTT of `addr`, or `None` if it has never been queried or has been forgotten.
+    pub fn get(&self, addr: IpAddr) -> Option<Duration> {
+        self.servers.lock().unwrap().rtts.get(&addr).map(|(srtt, _)| *srtt)
+    }
+
+    /// Folds a measured round trip to `addr` into its smoothed RTT.
+    pub fn record(&self, addr: IpAddr, rtt: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        let srtt = match servers.rtts.get(&addr) {
+            Some((srtt, _)) => srtt.mul_f64(1.0 - MEASUREMENT_WEIGHT) + rtt.mul_f64(MEASUREMENT_WEIGHT),
+            None => rtt,
+        };
+        servers.insert(addr, srtt, self.capacity);
+    }
+
+    /// Penalises `addr` for not responding within `timeout`, at least doubling its smoothed RTT.
+    pub fn penalize(&self, addr: IpAddr, timeout: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        let srtt = servers.rtts.get(&addr).map_or(timeout, |(srtt, _)| (*srtt * 2).max(timeout));
+        servers.insert(addr, srtt.min(MAX_RTT), self.capacity);
+    }
+
+    /// Sorts `candidates` from the fastest server to the slowest, and decays the smoothed RTTs
+    /// of all but the first.
+    pub fn order<T, F>(&self, candidates: &mut [T], addr: F) where F: Fn(&T) -> IpAddr {
+        let mut servers = self.servers.lock().unwrap();
+        candidates.sort_by_cached_key(|candidate| match servers.rtts.get(&addr(candidate)) {
+            Some((srtt, _)) => *srtt,
+            None => Duration::from_millis((random_u16() % MAX_UNKNOWN_RTT_MS) as u64),
+        });
+        for candidate in candidates.iter().skip(1) {
+            if let Some((srtt, _)) = servers.rtts.get_mut(&addr(candidate)) {
+                *srtt = srtt.mul_f64(DECAY);
+            }
+        }
+    }
+}
+
+impl Servers {
+    /// Sets the smoothed RTT of `addr`, forgetting the least recently measured servers to make
+    /// room for it.
+    fn insert(&mut self, addr: IpAddr, srtt: Duration, capacity: usize) {
+        if let Some((_, used)) = self.rtts.remove(&addr) {
+            self.recency.remove(&used);
+        }
+        while !self.rtts.is_empty() && self.rtts.len() >= capacity {
+            let (_, oldest) = self.recency.pop_first().expect("servers are in recency order");
+            self.rtts.remove(&oldest);
+        }
+        if capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        self.recency.insert(self.clock, addr);
+        self.rtts.insert(addr, (srtt, self.clock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn faster_servers_come_first_and_others_decay() {
+        let table = RttTable::default();
+        let (fast, slow, silent) = (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                                    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                                    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3)));
+        table.record(fast, Duration::from_millis(20));
+        table.record(fast, Duration::from_millis(10));
+        assert_eq!(table.get(fast), Some(Duration::from_millis(17)));
+        table.record(slow, Duration::from_millis(100));
+        table.penalize(silent, Duration::from_millis(800));
+        table.penalize(silent, Duration::from_millis(800));
+        assert_eq!(table.get(silent), Some(Duration::from_millis(1600)));
+
+        let mut candidates = [silent, slow, fast];
+        table.order(&mut candidates, |addr| *addr);
+        assert_eq!(candidates, [fast, slow, silent]);
+        assert_eq!(table.get(fast), Some(Duration::from_millis(17)));
+        assert_eq!(table.get(slow), Some(Duration::from_millis(98)));
+    }
+
+    #[test]
+    fn least_recently_measured_servers_are_forgotten() {
+        let table = RttTable::with_capacity(2);
+        let addr = |last: u8| IpAddr::V4(Ipv4Addr::new(192, 0, 2, last));
+        table.record(addr(1), Duration::from_millis(10));
+        table.record(addr(2), Duration::from_millis(20));
+        table.record(addr(1), Duration::from_millis(10));
+        table.penalize(addr(3), Duration::from_millis(800));
+
+        assert_eq!(table.get(addr(1)), Some(Duration::from_millis(10)));
+        assert_eq!(table.get(addr(2)), None);
+        assert_eq!(table.get(addr(3)), Some(Duration::from_millis(800)));
+    }
+}
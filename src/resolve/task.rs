@@ -0,0 +1,48 @@
+//! The little executor support the resolution logic needs to run without an async runtime.
+
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Runs `future` to completion on the current thread.
+///
+/// Only for futures which never wait: those of lookups over the blocking network, which does
+/// all its work as soon as it is polled.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("blocking lookups never wait"),
+    }
+}
+
+/// Runs `first` and `second` concurrently, giving up on `second` if `first` finishes with an
+/// output which makes it pointless.
+pub(crate) async fn join_unless<A, B, F>(first: A, second: B, pointless: F) -> (A::Output, Option<B::Output>)
+    where A: Future, B: Future, F: Fn(&A::Output) -> bool
+{
+    let (mut first, mut second) = (pin!(first), pin!(second));
+    let (mut a, mut b) = (None, None);
+    poll_fn(|cx| {
+        if a.is_none() {
+            if let Poll::Ready(output) = first.as_mut().poll(cx) {
+                a = Some(output);
+            }
+        }
+        match &a {
+            Some(output) if pointless(output) => return Poll::Ready(()),
+            _ => {}
+        }
+        if b.is_none() {
+            if let Poll::Ready(output) = second.as_mut().poll(cx) {
+                b = Some(output);
+            }
+        }
+        if a.is_some() && b.is_some() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }).await;
+    (a.expect("the first future finished"), b)
+}
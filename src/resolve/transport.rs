@@ -0,0 +1,210 @@
+use super::network::{answers, MAX_UDP_SIZE};
+use super::ResolveError;
+use crate::message::Message;
+use crate::random::random_u16;
+use crate::tcp::{read_tcp, write_tcp};
+use crate::zone::Zone;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How a `Resolver` sends a query to a server and gets its response back.
+///
+/// Lookups normally go over UDP, and over TCP when a response is truncated. Giving a resolver
+/// another transport with `Resolver::with_transport`, such as a `MemoryTransport`, lets tests
+/// run without a network.
+pub trait Transport: Send + Sync {
+    /// Sends `msg` to `server`, and waits up to `timeout` for the response to it. Returns `None`
+    /// if none arrives in time.
+    ///
+    /// Responses which do not answer `msg`, with its ID and question, should be ignored.
+    fn exchange(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError>;
+}
+
+/// Sends each query from a new socket bound to a random port.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpTransport;
+
+impl Transport for UdpTransport {
+    fn exchange(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        // The connected socket only receives datagrams from `server`
+        let socket = bind_random_port(server)?;
+        socket.connect(server)?;
+        socket.send(&msg.encode())?;
+        let until = Instant::now() + timeout;
+        let mut buf = vec![0u8; MAX_UDP_SIZE];
+        loop {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let size = match socket.recv(&mut buf) {
+                Ok(size) => size,
+                Err(e) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            match Message::decode(&buf[..size]) {
+                Ok(reply) if answers(&reply, msg) => return Ok(Some(reply)),
+                _ => log::debug!("discarding a response from {server} which does not match the query"),
+            }
+        }
+    }
+}
+
+/// Sends each query over a new TCP connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn exchange(&self, msg: &Message, server: SocketAddr, timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        let mut stream = match TcpStream::connect_timeout(&server, timeout) {
+            Ok(stream) => stream,
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write_tcp(&mut stream, msg)?;
+        loop {
+            let data = match read_tcp(&mut stream) {
+                Ok(data) => data,
+                Err(e) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let reply = Message::decode(&data)?;
+            if answers(&reply, msg) {
+                return Ok(Some(reply));
+            }
+            log::debug!("discarding a response from {server} which does not match the query");
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Binds a UDP socket for talking to `addr` to a random port, making responses harder to forge.
+pub(crate) fn bind_random_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let unspecified: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    for _ in 0..10 {
+        let port = 1024 + random_u16() % (u16::MAX - 1024);
+        match UdpSocket::bind((unspecified, port)) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
+    }
+    // Leave it to the operating system, which may also randomise the port
+    UdpSocket::bind((unspecified, 0))
+}
+
+/// Answers queries from zones held in memory, as if each were served from an address.
+///
+/// A server answers from the deepest of its zones holding the question name, and refuses
+/// questions outside all of them. Queries to addresses without zones go unanswered. Messages
+/// are encoded and decoded on the way, as they would be on the wire.
+///
+/// ```
+/// # use martin::resolve::{MemoryTransport, ResolverConfig};
+/// # use martin::{Resolver, ResourceRecord, Zone};
+/// # let root_zone = Zone::from_records(vec![ResourceRecord::SOA {
+/// #     name: ".".parse().unwrap(), class: martin::rr::Class::Internet, ttl: martin::Ttl::from_secs(60),
+/// #     mname: "a.root.".parse().unwrap(), rname: "admin.root.".parse().unwrap(), serial: martin::Serial(1),
+/// #     refresh: 60, retry: 60, expire: 60, minimum: 60,
+/// # }]).unwrap();
+/// let root = "192.0.2.1".parse().unwrap();
+/// let resolver = Resolver::new(ResolverConfig { root_hints: vec![root], ..ResolverConfig::default() })
+///     .with_transport(MemoryTransport::new().with_zone(root, root_zone));
+/// assert!(matches!(resolver.resolve("www.example."), Err(martin::ResolveError::NoSuchDomain)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransport {
+    servers: HashMap<IpAddr, Vec<Zone>>,
+}
+
+impl MemoryTransport {
+    /// Creates a `MemoryTransport` without any servers.
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+
+    /// Serves `zone` from `addr`, alongside any other zones already served from there.
+    pub fn with_zone(mut self, addr: IpAddr, zone: Zone) -> MemoryTransport {
+        self.servers.entry(addr).or_default().push(zone);
+        self
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn exchange(&self, msg: &Message, server: SocketAddr, _timeout: Duration) -> Result<Option<Message>, ResolveError> {
+        let zones = match self.servers.get(&server.ip()) {
+            Some(zones) => zones,
+            None => return Ok(None),
+        };
+        let query = Message::decode(&msg.encode())?;
+        let qname = query.questions.first().map(|q| &q.qname);
+        let zone = zones.iter()
+            .filter(|zone| qname.is_some_and(|qname| qname.is_subdomain_of(zone.origin())))
+            .max_by_key(|zone| zone.origin().name.len())
+            .unwrap_or(&zones[0]);
+        Ok(Some(Message::decode(&zone.respond(&query).encode())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Rcode;
+    use crate::question::{QType, Question};
+    use crate::rr::{Class, ResourceRecord, Type};
+    use crate::serial::Serial;
+    use crate::ttl::Ttl;
+
+    fn zone(origin: &str, host: &str) -> Zone {
+        Zone::from_records(vec![
+            ResourceRecord::SOA {
+                name: origin.parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(3600),
+                mname: "ns.example.".parse().unwrap(),
+                rname: "admin.example.".parse().unwrap(),
+                serial: Serial(1),
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 60,
+            },
+            ResourceRecord::A {
+                name: host.parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(60),
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        ]).unwrap()
+    }
+
+    #[test]
+    fn memory_servers_answer_from_their_deepest_zone() {
+        let addr: IpAddr = "192.0.2.53".parse().unwrap();
+        let transport = MemoryTransport::new()
+            .with_zone(addr, zone("example.", "www.example."))
+            .with_zone(addr, zone("sub.example.", "www.sub.example."));
+        let ask = |qname: &str, addr: IpAddr| {
+            let query = Message::query(7, false, Question::new(qname, QType::ByType(Type::A)).unwrap());
+            transport.exchange(&query, SocketAddr::new(addr, 53), Duration::from_secs(1)).unwrap()
+        };
+
+        let reply = ask("www.sub.example.", addr).unwrap();
+        assert_eq!(reply.id(), 7);
+        assert!(reply.authoritative());
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(ask("www.example.", addr).unwrap().answers.len(), 1);
+        assert_eq!(ask("www.example.net.", addr).unwrap().rcode(), Rcode::Refused);
+        assert!(ask("www.example.", "192.0.2.54".parse().unwrap()).is_none());
+    }
+}
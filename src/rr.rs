@@ -1,6 +1,8 @@
 //! Base types for dealing with resource records.
 
 use crate::names::Name;
+use crate::serial::Serial;
+use crate::ttl::Ttl;
 use std::convert::From;
 use std::fmt;
 use std::fmt::{Display, Formatter};
@@ -9,7 +11,7 @@ use std::net::{Ipv4Addr, Ipv6Addr};
 use byteorder::{BigEndian, WriteBytesExt};
 
 /// A `Type` field indicates the structure and content of a resource record.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Type {
     /// The `A` resource type, holding an IPv4 host address resource record.
     A,
@@ -17,6 +19,8 @@ pub enum Type {
     AAAA,
     /// The `CNAME` resource type, holding the canonical name for an alias.
     CNAME,
+    /// The `DNAME` resource type, redirecting a whole subtree of names (RFC 6672).
+    DNAME,
     /// The `PTR` resource type, pointing to a canonical name. Does not trigger `CNAME` processing.
     PTR,
     /// The `SOA` resource type, marks the start of a zone of authority.
@@ -29,6 +33,10 @@ pub enum Type {
     NS,
     /// The `TXT` resource type, holding text strings.
     TXT,
+    /// The `SRV` resource type, holding the location of a service (RFC 2782).
+    SRV,
+    /// The `TSIG` meta-RR type, holding a transaction signature.
+    TSIG,
     /// Indicates that the type is not known to this parser.
     Unknown {
         /// The value of the unknown type
@@ -37,7 +45,7 @@ pub enum Type {
 }
 
 /// Enum for valid `class` values from DNS resource records.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Class {
     /// The "Internet" class.
     Internet,
@@ -45,6 +53,10 @@ pub enum Class {
     Chaos,
     /// The "Hesoid" class.
     Hesoid,
+    /// The "NONE" class, used by dynamic updates (RFC 2136).
+    None,
+    /// The "ANY" class, used by queries and dynamic updates.
+    Any,
     /// An unknown class value.
     Unknown {
         /// The value of the unknown type
@@ -62,7 +74,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The IPv4 host address.
         addr: Ipv4Addr,
     },
@@ -73,7 +85,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The IPv6 host address.
         addr: Ipv6Addr,
     },
@@ -84,10 +96,21 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The canonical name for the alias referred to in `name`.
         cname: Name,
     },
+    /// The redirection of all names below `name` to the same names below `target`.
+    DNAME {
+        /// The `Name` this record applies to.
+        name: Name,
+        /// The `Class` this record applies to.
+        class: Class,
+        /// The "time to live" for this data, in seconds.
+        ttl: Ttl,
+        /// The name replacing `name` as the suffix of names below it.
+        target: Name,
+    },
     /// The start of a zone of authority.
     SOA {
         /// The `Name` this record applies to.
@@ -95,7 +118,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The <domain-name> of the name server that was the original or primary source of data
         /// for this zone.
         mname: Name,
@@ -103,9 +126,9 @@ pub enum ResourceRecord {
         rname: Name,
         /// The unsigned 32 bit version number of the original copy of the zone.
         ///
-        ///  Zone transfers preserve this value. This value wraps and should be compared using
+        /// Zone transfers preserve this value. This value wraps, and `Serial` compares it using
         /// sequence space arithmetic.
-        serial: u32,
+        serial: Serial,
         /// A 32 bit time interval before the zone should be refreshed.
         refresh: u32,
         /// A 32 bit time interval that should elapse before a failed refresh should be retried.
@@ -124,7 +147,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The canonical name pointed to in `name`.
         ptrname: Name,
     },
@@ -135,7 +158,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The preference given to this RR - lower values are preferred.
         preference: u16,
         /// A host willing to act as a mail exchange for the owner name.
@@ -148,7 +171,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// A host which should be authoritative for the specified class and domain.
         ns_name: Name,
     },
@@ -172,9 +195,47 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
-        /// One or more character strings.
-        data: Vec<String>,
+        ttl: Ttl,
+        /// One or more character strings, which may hold any bytes.
+        data: Vec<Vec<u8>>,
+    },
+    /// The location of a service (RFC 2782).
+    SRV {
+        /// The `Name` this record applies to, in the form `_service._proto.name`.
+        name: Name,
+        /// The `Class` this record applies to.
+        class: Class,
+        /// The "time to live" for this data, in seconds.
+        ttl: Ttl,
+        /// The priority of the target host - lower values are preferred.
+        priority: u16,
+        /// The relative weight of targets with the same priority.
+        weight: u16,
+        /// The port on the target host of the service.
+        port: u16,
+        /// The host providing the service.
+        target: Name,
+    },
+    /// A transaction signature meta-record (RFC 8945).
+    ///
+    /// The class is always `ANY` and the TTL always zero.
+    TSIG {
+        /// The name of the key used to sign the message.
+        name: Name,
+        /// The name of the MAC algorithm.
+        algorithm: Name,
+        /// The signing time, in seconds since the UNIX epoch. Only 48 bits are transmitted.
+        time_signed: u64,
+        /// The permitted error in `time_signed`, in seconds.
+        fudge: u16,
+        /// The message authentication code.
+        mac: Vec<u8>,
+        /// The message ID of the message when it was originally signed.
+        original_id: u16,
+        /// The extended response code for TSIG processing.
+        error: u16,
+        /// Additional data; the server's time for `BADTIME` errors.
+        other: Vec<u8>,
     },
     /// A yet-unknown type of resource record.
     Unknown {
@@ -185,7 +246,7 @@ pub enum ResourceRecord {
         /// The `Class` this record applies to.
         class: Class,
         /// The "time to live" for this data, in seconds.
-        ttl: i32,
+        ttl: Ttl,
         /// The data contained by the unknown record type.
         data: Vec<u8>,
     },
@@ -197,43 +258,111 @@ impl ResourceRecord {
             ResourceRecord::A { name, .. } => Some(name),
             ResourceRecord::AAAA { name, .. } => Some(name),
             ResourceRecord::CNAME { name, .. } => Some(name),
+            ResourceRecord::DNAME { name, .. } => Some(name),
             ResourceRecord::SOA { name, .. } => Some(name),
             ResourceRecord::PTR { name, .. } => Some(name),
             ResourceRecord::MX { name, .. } => Some(name),
             ResourceRecord::NS { name, .. } => Some(name),
             ResourceRecord::OPT { .. } => None,
             ResourceRecord::TXT { name, .. } => Some(name),
+            ResourceRecord::SRV { name, .. } => Some(name),
+            ResourceRecord::TSIG { name, .. } => Some(name),
             ResourceRecord::Unknown { name, .. } => Some(name),
         }
     }
+    pub(crate) fn name_mut(&mut self) -> Option<&mut Name> {
+        match self {
+            ResourceRecord::A { name, .. } |
+            ResourceRecord::AAAA { name, .. } |
+            ResourceRecord::CNAME { name, .. } |
+            ResourceRecord::DNAME { name, .. } |
+            ResourceRecord::SOA { name, .. } |
+            ResourceRecord::PTR { name, .. } |
+            ResourceRecord::MX { name, .. } |
+            ResourceRecord::NS { name, .. } |
+            ResourceRecord::TXT { name, .. } |
+            ResourceRecord::SRV { name, .. } |
+            ResourceRecord::TSIG { name, .. } |
+            ResourceRecord::Unknown { name, .. } => Some(name),
+            ResourceRecord::OPT { .. } => None,
+        }
+    }
     pub fn rtype(&self) -> Type {
         match self {
             ResourceRecord::A {..} => Type::A,
             ResourceRecord::AAAA {..} => Type::AAAA,
             ResourceRecord::CNAME {..} => Type::CNAME,
+            ResourceRecord::DNAME {..} => Type::DNAME,
             ResourceRecord::SOA {..} => Type::SOA,
             ResourceRecord::PTR {..} => Type::PTR,
             ResourceRecord::MX {..} => Type::MX,
             ResourceRecord::NS {..} => Type::NS,
             ResourceRecord::OPT {..} => Type::OPT,
             ResourceRecord::TXT {..} => Type::TXT,
+            ResourceRecord::SRV {..} => Type::SRV,
+            ResourceRecord::TSIG {..} => Type::TSIG,
             ResourceRecord::Unknown {rtype, ..} => *rtype,
         }
     }
-    pub fn ttl(&self) -> Option<i32> {
+    pub fn class(&self) -> Option<Class> {
+        match self {
+            ResourceRecord::A { class, .. } => Some(*class),
+            ResourceRecord::AAAA { class, .. } => Some(*class),
+            ResourceRecord::CNAME { class, .. } => Some(*class),
+            ResourceRecord::DNAME { class, .. } => Some(*class),
+            ResourceRecord::SOA { class, .. } => Some(*class),
+            ResourceRecord::PTR { class, .. } => Some(*class),
+            ResourceRecord::MX { class, .. } => Some(*class),
+            ResourceRecord::NS { class, .. } => Some(*class),
+            ResourceRecord::OPT { .. } => None,
+            ResourceRecord::TXT { class, .. } => Some(*class),
+            ResourceRecord::SRV { class, .. } => Some(*class),
+            ResourceRecord::TSIG { .. } => Some(Class::Any),
+            ResourceRecord::Unknown { class, .. } => Some(*class),
+        }
+    }
+    pub fn ttl(&self) -> Option<Ttl> {
         match self {
             ResourceRecord::A { ttl, .. } => Some(*ttl),
             ResourceRecord::AAAA { ttl, .. } => Some(*ttl),
             ResourceRecord::CNAME { ttl, .. } => Some(*ttl),
+            ResourceRecord::DNAME { ttl, .. } => Some(*ttl),
             ResourceRecord::SOA { ttl, .. } => Some(*ttl),
             ResourceRecord::PTR { ttl, .. } => Some(*ttl),
             ResourceRecord::MX { ttl, .. } => Some(*ttl),
             ResourceRecord::NS { ttl, .. } => Some(*ttl),
             ResourceRecord::OPT { .. } => None,
             ResourceRecord::TXT { ttl, .. } => Some(*ttl),
+            ResourceRecord::SRV { ttl, .. } => Some(*ttl),
+            ResourceRecord::TSIG { .. } => Some(Ttl::ZERO),
             ResourceRecord::Unknown { ttl, .. } => Some(*ttl),
         }
     }
+
+    /// Returns a copy of this record with the class and TTL replaced.
+    ///
+    /// `OPT` and `TSIG` pseudo-records have fixed values, and are returned unchanged.
+    pub fn with_class_and_ttl(&self, new_class: Class, new_ttl: Ttl) -> ResourceRecord {
+        let mut rr = self.clone();
+        match rr {
+            ResourceRecord::A { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::AAAA { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::CNAME { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::DNAME { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::SOA { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::PTR { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::MX { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::NS { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::TXT { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::SRV { ref mut class, ref mut ttl, .. } |
+            ResourceRecord::Unknown { ref mut class, ref mut ttl, .. } => {
+                *class = new_class;
+                *ttl = new_ttl;
+            }
+            ResourceRecord::OPT { .. } | ResourceRecord::TSIG { .. } => {}
+        }
+        rr
+    }
 }
 
 impl Display for ResourceRecord {
@@ -242,12 +371,21 @@ impl Display for ResourceRecord {
             ResourceRecord::A { name, class, ttl,  addr } => write!(f, "{name} {} {class} {ttl} {addr}", Type::A),
             ResourceRecord::AAAA { name, class, ttl, addr } => write!(f, "{name} {} {class} {ttl} {addr}", Type::AAAA),
             ResourceRecord::CNAME { name, class, ttl, cname } => write!(f, "{name} {} {class} {ttl} {cname}", Type::CNAME),
+            ResourceRecord::DNAME { name, class, ttl, target } => write!(f, "{name} {} {class} {ttl} {target}", Type::DNAME),
             ResourceRecord::SOA { name, class, ttl, .. } => write!(f, "{name} {} {class} {ttl}", Type::SOA),
             ResourceRecord::PTR { name, class, ttl, ptrname } => write!(f, "{name} {} {class} {ttl} {ptrname}", Type::PTR),
             ResourceRecord::MX { name, class, ttl,preference, exchange } => write!(f, "{name} {} {class} {ttl} {preference} {exchange}", Type::MX),
             ResourceRecord::NS { name, class, ttl, ns_name } => write!(f, "{name} {} {class} {ttl} {ns_name}", Type::NS),
             ResourceRecord::OPT { .. } => write!(f, ". {}", Type::OPT),
-            ResourceRecord::TXT { name, class, ttl, data } => write!(f, "{name} {} {class} {ttl} {data:?}", Type::TXT),
+            ResourceRecord::TXT { name, class, ttl, data } => {
+                write!(f, "{name} {} {class} {ttl}", Type::TXT)?;
+                for string in data {
+                    write!(f, " {:?}", String::from_utf8_lossy(string))?;
+                }
+                Ok(())
+            }
+            ResourceRecord::SRV { name, class, ttl, priority, weight, port, target } => write!(f, "{name} {} {class} {ttl} {priority} {weight} {port} {target}", Type::SRV),
+            ResourceRecord::TSIG { name, algorithm, time_signed, fudge, error, .. } => write!(f, "{name} {} {} 0 {algorithm} {time_signed} {fudge} {error}", Type::TSIG, Class::Any),
             ResourceRecord::Unknown { name, rtype, class, ttl, data } => write!(f, "{name} {rtype} {class} {ttl} {data:?}"),
         }
     }
@@ -259,6 +397,8 @@ impl From<u16> for Class {
             1u16 => Class::Internet,
             3u16 => Class::Chaos,
             4u16 => Class::Hesoid,
+            254u16 => Class::None,
+            255u16 => Class::Any,
             _ => Class::Unknown { value },
         }
     }
@@ -270,6 +410,8 @@ impl From<Class> for u16 {
             Class::Internet => 1u16,
             Class::Chaos => 3u16,
             Class::Hesoid => 4u16,
+            Class::None => 254u16,
+            Class::Any => 255u16,
             Class::Unknown { value: x } => x,
         }
     }
@@ -286,7 +428,10 @@ impl From<u16> for Type {
             15u16 => Type::MX,
             16u16 => Type::TXT,
             28u16 => Type::AAAA,
+            33u16 => Type::SRV,
+            39u16 => Type::DNAME,
             41u16 => Type::OPT,
+            250u16 => Type::TSIG,
             _ => Type::Unknown { value },
         }
     }
@@ -303,7 +448,10 @@ impl From<Type> for u16 {
             Type::MX => 15u16,
             Type::TXT => 16u16,
             Type::AAAA => 28u16,
+            Type::SRV => 33u16,
+            Type::DNAME => 39u16,
             Type::OPT => 41u16,
+            Type::TSIG => 250u16,
             Type::Unknown { value: x } => x,
         }
     }
@@ -315,6 +463,8 @@ impl fmt::Display for Class {
             Class::Internet => write!(f, "IN"),
             Class::Chaos => write!(f, "CH"),
             Class::Hesoid => write!(f, "HS"),
+            Class::None => write!(f, "NONE"),
+            Class::Any => write!(f, "ANY"),
             Class::Unknown { value: x } => write!(f, "0x{:x}", x),
         }
     }
@@ -326,12 +476,15 @@ impl fmt::Display for Type {
             Type::A => write!(f, "A"),
             Type::AAAA => write!(f, "AAAA"),
             Type::CNAME => write!(f, "CNAME"),
+            Type::DNAME => write!(f, "DNAME"),
             Type::SOA => write!(f, "SOA"),
             Type::PTR => write!(f, "PTR"),
             Type::OPT => write!(f, "OPT"),
             Type::MX => write!(f, "MX"),
             Type::NS => write!(f, "NS"),
             Type::TXT => write!(f, "TXT"),
+            Type::SRV => write!(f, "SRV"),
+            Type::TSIG => write!(f, "TSIG"),
             Type::Unknown { value: x } => write!(f, "0x{:x}", x),
         }
     }
@@ -363,14 +516,29 @@ impl ResourceRecord {
                 name.write_to(cursor)?;
                 cursor.write_u16::<BigEndian>(Type::CNAME.into())?;
                 cursor.write_u16::<BigEndian>(class.into())?;
-                cursor.write_i32::<BigEndian>(ttl)?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
 
                 let start = cursor.position();
                 cursor.write_u16::<BigEndian>(0)?;
                 cname.write_to(cursor)?;
                 let end = cursor.position();
                 cursor.set_position(start);
-                cursor.write_u16::<BigEndian>((end - start) as u16)?;
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
+                cursor.set_position(end);
+                Ok(())
+            }
+            ResourceRecord::DNAME { ref name, class, ttl, ref target } => {
+                name.write_to(cursor)?;
+                cursor.write_u16::<BigEndian>(Type::DNAME.into())?;
+                cursor.write_u16::<BigEndian>(class.into())?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
+
+                let start = cursor.position();
+                cursor.write_u16::<BigEndian>(0)?;
+                target.write_to(cursor)?;
+                let end = cursor.position();
+                cursor.set_position(start);
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
                 cursor.set_position(end);
                 Ok(())
             }
@@ -389,14 +557,14 @@ impl ResourceRecord {
                 name.write_to(cursor)?;
                 cursor.write_u16::<BigEndian>(Type::SOA.into())?;
                 cursor.write_u16::<BigEndian>(class.into())?;
-                cursor.write_i32::<BigEndian>(ttl)?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
 
                 let start = cursor.position();
                 cursor.write_u16::<BigEndian>(0)?;
 
                 mname.write_to(cursor)?;
                 rname.write_to(cursor)?;
-                cursor.write_u32::<BigEndian>(serial)?;
+                cursor.write_u32::<BigEndian>(serial.into())?;
                 cursor.write_u32::<BigEndian>(refresh)?;
                 cursor.write_u32::<BigEndian>(retry)?;
                 cursor.write_u32::<BigEndian>(expire)?;
@@ -404,18 +572,32 @@ impl ResourceRecord {
 
                 let end = cursor.position();
                 cursor.set_position(start);
-                cursor.write_u16::<BigEndian>((end - start) as u16)?;
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
                 cursor.set_position(end);
                 Ok(())
             }
-            ResourceRecord::PTR { ref name, class, ttl, .. } => {
-                write_data(name, Type::PTR, class, ttl, &[], cursor)
+            ResourceRecord::PTR { ref name, class, ttl, ref ptrname } => {
+                name.write_to(cursor)?;
+                cursor.write_u16::<BigEndian>(Type::PTR.into())?;
+                cursor.write_u16::<BigEndian>(class.into())?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
+
+                let start = cursor.position();
+                cursor.write_u16::<BigEndian>(0)?;
+
+                ptrname.write_to(cursor)?;
+
+                let end = cursor.position();
+                cursor.set_position(start);
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
+                cursor.set_position(end);
+                Ok(())
             }
             ResourceRecord::MX { ref name, class, ttl, preference, ref exchange } => {
                 name.write_to(cursor)?;
                 cursor.write_u16::<BigEndian>(Type::MX.into())?;
                 cursor.write_u16::<BigEndian>(class.into())?;
-                cursor.write_i32::<BigEndian>(ttl)?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
 
                 let start = cursor.position();
                 cursor.write_u16::<BigEndian>(0)?;
@@ -425,7 +607,7 @@ impl ResourceRecord {
 
                 let end = cursor.position();
                 cursor.set_position(start);
-                cursor.write_u16::<BigEndian>((end - start) as u16)?;
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
                 cursor.set_position(end);
                 Ok(())
             }
@@ -433,7 +615,7 @@ impl ResourceRecord {
                 name.write_to(cursor)?;
                 cursor.write_u16::<BigEndian>(Type::NS.into())?;
                 cursor.write_u16::<BigEndian>(class.into())?;
-                cursor.write_i32::<BigEndian>(ttl)?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
 
                 let start = cursor.position();
                 cursor.write_u16::<BigEndian>(0)?;
@@ -442,12 +624,77 @@ impl ResourceRecord {
 
                 let end = cursor.position();
                 cursor.set_position(start);
-                cursor.write_u16::<BigEndian>((end - start) as u16)?;
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
                 cursor.set_position(end);
                 Ok(())
             }
-            ResourceRecord::TXT { ref name, class, ttl, .. } => {
-                write_data(name, Type::TXT, class, ttl, &[], cursor)
+            ResourceRecord::TXT { ref name, class, ttl, ref data } => {
+                let mut rdata = Vec::new();
+                for string in data {
+                    // Character strings longer than 255 bytes are split, as they are in zone files
+                    if string.is_empty() {
+                        rdata.write_u8(0)?;
+                    }
+                    for chunk in string.chunks(255) {
+                        rdata.write_u8(chunk.len() as u8)?;
+                        rdata.write_all(chunk)?;
+                    }
+                }
+                write_data(name, Type::TXT, class, ttl, &rdata, cursor)
+            }
+            ResourceRecord::SRV { ref name, class, ttl, priority, weight, port, ref target } => {
+                name.write_to(cursor)?;
+                cursor.write_u16::<BigEndian>(Type::SRV.into())?;
+                cursor.write_u16::<BigEndian>(class.into())?;
+                cursor.write_u32::<BigEndian>(ttl.into())?;
+
+                let start = cursor.position();
+                cursor.write_u16::<BigEndian>(0)?;
+
+                cursor.write_u16::<BigEndian>(priority)?;
+                cursor.write_u16::<BigEndian>(weight)?;
+                cursor.write_u16::<BigEndian>(port)?;
+                target.write_to(cursor)?;
+
+                let end = cursor.position();
+                cursor.set_position(start);
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
+                cursor.set_position(end);
+                Ok(())
+            }
+            ResourceRecord::TSIG {
+                ref name,
+                ref algorithm,
+                time_signed,
+                fudge,
+                ref mac,
+                original_id,
+                error,
+                ref other
+            } => {
+                name.write_to(cursor)?;
+                cursor.write_u16::<BigEndian>(Type::TSIG.into())?;
+                cursor.write_u16::<BigEndian>(Class::Any.into())?;
+                cursor.write_u32::<BigEndian>(0)?;
+
+                let start = cursor.position();
+                cursor.write_u16::<BigEndian>(0)?;
+
+                algorithm.write_to(cursor)?;
+                cursor.write_u48::<BigEndian>(time_signed)?;
+                cursor.write_u16::<BigEndian>(fudge)?;
+                cursor.write_u16::<BigEndian>(mac.len() as u16)?;
+                cursor.write_all(mac)?;
+                cursor.write_u16::<BigEndian>(original_id)?;
+                cursor.write_u16::<BigEndian>(error)?;
+                cursor.write_u16::<BigEndian>(other.len() as u16)?;
+                cursor.write_all(other)?;
+
+                let end = cursor.position();
+                cursor.set_position(start);
+                cursor.write_u16::<BigEndian>((end - start - 2) as u16)?;
+                cursor.set_position(end);
+                Ok(())
             }
             ResourceRecord::Unknown { ref name, rtype, class, ttl, ref data } => {
                 write_data(name, rtype, class, ttl, data, cursor)
@@ -456,11 +703,11 @@ impl ResourceRecord {
     }
 }
 
-fn write_data<T>(name: &Name, rtype: Type, rclass: Class, ttl: i32, data: &[u8], cursor: &mut Cursor<T>) -> std::io::Result<()> where Cursor<T>: Write {
+fn write_data<T>(name: &Name, rtype: Type, rclass: Class, ttl: Ttl, data: &[u8], cursor: &mut Cursor<T>) -> std::io::Result<()> where Cursor<T>: Write {
     name.write_to(cursor)?;
     cursor.write_u16::<BigEndian>(rtype.into())?;
     cursor.write_u16::<BigEndian>(rclass.into())?;
-    cursor.write_i32::<BigEndian>(ttl)?;
+    cursor.write_u32::<BigEndian>(ttl.into())?;
     cursor.write_u16::<BigEndian>(data.len() as u16)?;
     cursor.write_all(data)?;
     Ok(())
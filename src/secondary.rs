@@ -0,0 +1,608 @@
+//! Maintenance of secondary zones, driven by the timers in the primary's `SOA` record.
+
+use crate::header::Rcode;
+use crate::message::Message;
+use crate::names::Name;
+use crate::notify::{query_serial, NotifyError, NotifyHandler};
+use crate::question::{QType, Question};
+use crate::random::random_u16;
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::serial::Serial;
+use crate::tcp::{read_tcp, write_tcp};
+use crate::tsig::{self, Key, ResponseVerifier, TsigError};
+use crate::zone::{Zone, ZoneError};
+use std::cmp::Ordering;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Settings for refreshing secondary zones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecondaryConfig {
+    /// How long to wait for each response from the primary.
+    pub timeout: Duration,
+    /// How many times to retransmit an unanswered `SOA` query.
+    pub retries: usize,
+    /// How long to wait before retrying a zone which has never been transferred.
+    pub initial_retry: Duration,
+    /// How long a whole zone transfer may take before it is abandoned and retried later.
+    pub max_transfer_time: Duration,
+}
+
+impl Default for SecondaryConfig {
+    fn default() -> SecondaryConfig {
+        SecondaryConfig {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            initial_retry: Duration::from_secs(60),
+            max_transfer_time: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Errors that may occur while refreshing a secondary zone.
+#[derive(Debug, Error)]
+pub enum SecondaryError {
+    #[error("SOA query to the primary failed: {0}")]
+    QueryFailed(#[from] NotifyError),
+
+    #[error("the primary refused the zone transfer with {0:?}")]
+    TransferRefused(Rcode),
+
+    #[error("the zone transfer was malformed: {0}")]
+    TransferMalformed(&'static str),
+
+    #[error("the zone transfer took too long")]
+    TransferTimedOut,
+
+    #[error("the zone transfer failed authentication: {0}")]
+    TransferUnauthenticated(#[from] TsigError),
+
+    #[error("the transferred zone is invalid: {0}")]
+    InvalidZone(#[from] ZoneError),
+
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+
+    #[error("deserialization error: {0}")]
+    DeseralizationFailed(#[from] nom::Err<nom::error::Error<Vec<u8>>>),
+}
+
+/// The result of a refresh check which did not fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refresh {
+    /// The refresh timer has not yet fired.
+    NotDue,
+    /// The primary's serial has not moved on.
+    UpToDate,
+    /// A new copy of the zone was transferred, with the given serial.
+    Transferred(Serial),
+}
+
+/// A zone copied from a primary server and kept up to date.
+#[derive(Debug)]
+pub struct SecondaryZone {
+    name: Name,
+    class: Class,
+    primary: SocketAddr,
+    key: Option<Key>,
+    config: SecondaryConfig,
+    zone: Option<Zone>,
+    next_check: Instant,
+    expires_at: Option<Instant>,
+    failures: u32,
+}
+
+impl SecondaryZone {
+    /// Create a `SecondaryZone` of the Internet class, which will be transferred from `primary`
+    /// on the first `poll`.
+    pub fn new(name: Name, primary: SocketAddr, config: SecondaryConfig, now: Instant) -> SecondaryZone {
+        SecondaryZone {
+            name,
+            class: Class::Internet,
+            primary,
+            key: None,
+            config,
+            zone: None,
+            next_check: now,
+            expires_at: None,
+            failures: 0,
+        }
+    }
+
+    /// Polls and transfers the zone in `class`, rather than the Internet class.
+    pub fn with_class(mut self, class: Class) -> SecondaryZone {
+        self.class = class;
+        self
+    }
+
+    /// Signs transfers of the zone with `key`, and accepts only those the primary signed with it.
+    pub fn with_key(mut self, key: Key) -> SecondaryZone {
+        self.key = Some(key);
+        self
+    }
+
+    /// The name of the zone.
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// The class of the zone.
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    /// The primary server the zone is transferred from.
+    pub fn primary(&self) -> SocketAddr {
+        self.primary
+    }
+
+    /// When the zone is next due for a refresh check.
+    pub fn next_check(&self) -> Instant {
+        self.next_check
+    }
+
+    /// Determines whether the zone has gone `expire` seconds without a successful refresh.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now >= expires_at,
+            None => true,
+        }
+    }
+
+    /// The zone data, if it has been transferred and has not expired.
+    pub fn zone(&self, now: Instant) -> Option<&Zone> {
+        if self.is_expired(now) {
+            None
+        } else {
+            self.zone.as_ref()
+        }
+    }
+
+    /// Makes the zone due for a refresh check immediately, as on receipt of a `NOTIFY`.
+    pub fn refresh_now(&mut self, now: Instant) {
+        self.next_check = now;
+    }
+
+    /// Checks the primary's serial if the refresh timer has fired, transferring the zone if the
+    /// serial has moved on.
+    ///
+    /// On failure the check is rescheduled after the `SOA` retry interval, doubling for each
+    /// consecutive failure but never exceeding the refresh interval.
+    pub fn poll(&mut self, now: Instant) -> Result<Refresh, SecondaryError> {
+        if now < self.next_check {
+            return Ok(Refresh::NotDue);
+        }
+        match self.refresh(now) {
+            Ok(refresh) => {
+                self.failures = 0;
+                let (refresh_interval, _, expire) = self.timers();
+                self.next_check = now + refresh_interval;
+                self.expires_at = Some(now + expire);
+                Ok(refresh)
+            }
+            Err(e) => {
+                let (refresh_interval, retry, _) = self.timers();
+                let backoff = retry.saturating_mul(1 << self.failures.min(16));
+                self.next_check = now + backoff.min(refresh_interval.max(retry));
+                self.failures += 1;
+                Err(e)
+            }
+        }
+    }
+
+    fn refresh(&mut self, now: Instant) -> Result<Refresh, SecondaryError> {
+        let serial = query_serial(self.primary, &self.name, self.class, self.config.timeout, self.config.retries)?;
+        if let Some(zone) = self.zone.as_ref() {
+            if serial.partial_cmp(&zone.serial()) != Some(Ordering::Greater) && !self.is_expired(now) {
+                return Ok(Refresh::UpToDate);
+            }
+        }
+        let deadline = Instant::now() + self.config.max_transfer_time;
+        let zone = transfer_zone(self.primary, &self.name, self.class, self.key.as_ref(), self.config.timeout, deadline)?;
+        let serial = zone.serial();
+        self.zone = Some(zone);
+        Ok(Refresh::Transferred(serial))
+    }
+
+    /// The refresh, retry and expire intervals from the current `SOA` record.
+    fn timers(&self) -> (Duration, Duration, Duration) {
+        match self.zone.as_ref().map(|z| z.soa()) {
+            Some(ResourceRecord::SOA { refresh, retry, expire, .. }) => (
+                Duration::from_secs(*refresh as u64),
+                Duration::from_secs(*retry as u64),
+                Duration::from_secs(*expire as u64),
+            ),
+            _ => (self.config.initial_retry, self.config.initial_retry, Duration::ZERO),
+        }
+    }
+}
+
+/// Keeps a set of secondary zones up to date.
+#[derive(Debug, Default)]
+pub struct SecondaryManager {
+    zones: Vec<SecondaryZone>,
+}
+
+impl SecondaryManager {
+    /// Create an empty `SecondaryManager`.
+    pub fn new() -> SecondaryManager {
+        SecondaryManager { zones: Vec::new() }
+    }
+
+    /// Adds a zone to be maintained.
+    pub fn add(&mut self, zone: SecondaryZone) {
+        self.zones.push(zone);
+    }
+
+    /// The zones being maintained.
+    pub fn zones(&self) -> &[SecondaryZone] {
+        &self.zones
+    }
+
+    /// The zone data for `name`, if it is a secondary zone which is currently being served.
+    pub fn zone(&self, name: &Name, now: Instant) -> Option<&Zone> {
+        self.zones.iter()
+            .find(|z| z.name.eq_ignore_case(name))
+            .and_then(|z| z.zone(now))
+    }
+
+    /// The earliest time at which a zone is due for a refresh check.
+    pub fn next_check(&self) -> Option<Instant> {
+        self.zones.iter().map(|z| z.next_check).min()
+    }
+
+    /// Polls every zone which is due for a refresh check.
+    pub fn poll(&mut self, now: Instant) -> Vec<(Name, Result<Refresh, SecondaryError>)> {
+        self.zones.iter_mut()
+            .filter(|z| now >= z.next_check)
+            .map(|z| (z.name.clone(), z.poll(now)))
+            .collect()
+    }
+}
+
+impl NotifyHandler for SecondaryManager {
+    /// Schedules an immediate refresh check, if the notification came from the zone's primary.
+    fn notify(&mut self, zone: &Name, _serial: Option<Serial>, from: SocketAddr) -> Result<(), Rcode> {
+        let secondary = self.zones.iter_mut()
+            .find(|z| z.name.eq_ignore_case(zone))
+            .ok_or(Rcode::NotAuth)?;
+        if secondary.primary.ip() != from.ip() {
+            return Err(Rcode::Refused);
+        }
+        secondary.refresh_now(Instant::now());
+        Ok(())
+    }
+}
+
+/// Transfers a complete copy of `zone`, in `class`, from `primary` using `AXFR`.
+///
+/// With a `key`, the request is signed and every response must verify against it.
+///
+/// Each response must arrive within `timeout`, and the whole transfer must be done by
+/// `deadline`, so a primary cannot hold it open by sending messages slowly.
+pub fn transfer_zone(primary: SocketAddr,
+                     zone: &Name,
+                     class: Class,
+                     key: Option<&Key>,
+                     timeout: Duration,
+                     deadline: Instant)
+                     -> Result<Zone, SecondaryError> {
+    let mut stream = TcpStream::connect_timeout(&primary, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let id = random_u16();
+    let question = Question {
+        qname: zone.clone(),
+        qtype: QType::Axfr,
+        qclass: class,
+    };
+    let mut request = Message::query(id, false, question);
+    let mut verifier = key.map(|key| {
+        let request_mac = tsig::sign_request(&mut request, key, unix_time());
+        ResponseVerifier::new(key, &request_mac)
+    });
+    write_tcp(&mut stream, &request)?;
+
+    let mut records: Vec<ResourceRecord> = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(SecondaryError::TransferTimedOut);
+        }
+        stream.set_read_timeout(Some(timeout.min(remaining)))?;
+        let data = match read_tcp(&mut stream) {
+            Ok(data) => data,
+            Err(_) if Instant::now() >= deadline => return Err(SecondaryError::TransferTimedOut),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.verify(&data, unix_time())?;
+        }
+        let msg = Message::decode(&data)?;
+        if !msg.is_response() || msg.id() != id {
+            return Err(SecondaryError::TransferMalformed("unexpected message in transfer"));
+        }
+        if msg.rcode() != Rcode::NoError {
+            return Err(SecondaryError::TransferRefused(msg.rcode()));
+        }
+        if msg.answers.is_empty() {
+            return Err(SecondaryError::TransferMalformed("empty transfer message"));
+        }
+        for rr in msg.answers {
+            let is_soa = rr.rtype() == Type::SOA;
+            if records.is_empty() && !is_soa {
+                return Err(SecondaryError::TransferMalformed("transfer must begin with the SOA record"));
+            }
+            if records.is_empty() && !rr.name().is_some_and(|name| name.eq_ignore_case(zone)) {
+                return Err(SecondaryError::TransferMalformed("the SOA record is not for the requested zone"));
+            }
+            // The transfer ends with a repeat of the initial SOA record
+            if is_soa && !records.is_empty() {
+                if let Some(verifier) = verifier.as_ref() {
+                    verifier.finish()?;
+                }
+                return Ok(Zone::from_records(records)?);
+            }
+            records.push(rr);
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ttl::Ttl;
+    use std::net::{TcpListener, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn soa(serial: u32) -> ResourceRecord {
+        ResourceRecord::SOA {
+            name: "example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(3600),
+            mname: "ns1.example.com.".parse().unwrap(),
+            rname: "hostmaster.example.com.".parse().unwrap(),
+            serial: Serial(serial),
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        }
+    }
+
+    fn zone(serial: u32) -> Zone {
+        Zone::from_records(vec![
+            soa(serial),
+            ResourceRecord::A {
+                name: "www.example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(300),
+                addr: [192, 0, 2, serial as u8].into(),
+            },
+        ]).unwrap()
+    }
+
+    fn key(secret: &[u8]) -> Key {
+        Key::new("transfer.example.com.".parse().unwrap(), tsig::Algorithm::HmacSha256, secret.to_vec())
+    }
+
+    /// A primary serving `SOA` queries over UDP and `AXFR` over TCP on the same port.
+    fn primary(zone: Arc<Mutex<Zone>>) -> SocketAddr {
+        signing_primary(zone, None)
+    }
+
+    /// A primary which, with a `key`, only transfers the zone for signed requests and signs
+    /// each message of the transfer.
+    fn signing_primary(zone: Arc<Mutex<Zone>>, key: Option<Key>) -> SocketAddr {
+        // The UDP port may already be taken, so keep trying fresh ports
+        let (tcp, udp) = (0..10)
+            .find_map(|_| {
+                let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+                let udp = UdpSocket::bind(tcp.local_addr().unwrap()).ok()?;
+                Some((tcp, udp))
+            })
+            .unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let udp_zone = zone.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((size, from)) = udp.recv_from(&mut buf) {
+                let query = Message::decode(&buf[..size]).unwrap();
+                let zone = udp_zone.lock().unwrap().clone();
+                let mut response = Message::response(query.clone(), false);
+                if query.questions[0].qclass == zone.class() {
+                    response.answers.push(zone.soa().clone());
+                    response.header.answer_count = 1;
+                } else {
+                    response.header.rcode = Rcode::Refused;
+                }
+                udp.send_to(&response.encode(), from).unwrap();
+            }
+        });
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                let mut stream = stream.unwrap();
+                let data = read_tcp(&mut stream).unwrap();
+                let request = Message::decode(&data).unwrap();
+                let keys: Vec<Key> = key.iter().cloned().collect();
+                let mut signer = match key.as_ref().map(|_| tsig::verify_request(&data, &keys, unix_time())) {
+                    Some(Ok((key, mac))) => Some(tsig::ResponseSigner::new(key, &mac)),
+                    Some(Err(e)) => {
+                        write_tcp(&mut stream, &tsig::error_response(&request, &keys, &e, unix_time())).unwrap();
+                        continue;
+                    }
+                    None => None,
+                };
+                let zone = zone.lock().unwrap().clone();
+                if request.questions[0].qclass != zone.class() {
+                    let mut response = Message::response(request.clone(), false);
+                    response.header.rcode = Rcode::Refused;
+                    write_tcp(&mut stream, &response).unwrap();
+                    continue;
+                }
+                // Split the transfer across two messages
+                let mut records = zone.records().to_vec();
+                records.push(zone.soa().clone());
+                for chunk in records.chunks(2) {
+                    let mut response = Message::response(request.clone(), false);
+                    response.answers = chunk.to_vec();
+                    response.header.answer_count = chunk.len() as u16;
+                    if let Some(signer) = signer.as_mut() {
+                        signer.sign(&mut response, unix_time());
+                    }
+                    write_tcp(&mut stream, &response).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    fn config() -> SecondaryConfig {
+        SecondaryConfig {
+            timeout: Duration::from_millis(200),
+            retries: 0,
+            initial_retry: Duration::from_secs(30),
+            max_transfer_time: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn transfers_when_serial_moves() {
+        let data = Arc::new(Mutex::new(zone(1)));
+        let addr = primary(data.clone());
+        let start = Instant::now();
+        let mut secondary = SecondaryZone::new("example.com.".parse().unwrap(), addr, config(), start);
+        assert!(secondary.zone(start).is_none());
+
+        assert_eq!(secondary.poll(start).unwrap(), Refresh::Transferred(Serial(1)));
+        assert_eq!(secondary.zone(start), Some(&zone(1)));
+        assert_eq!(secondary.next_check(), start + Duration::from_secs(3600));
+        assert_eq!(secondary.poll(start).unwrap(), Refresh::NotDue);
+
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(secondary.poll(later).unwrap(), Refresh::UpToDate);
+
+        *data.lock().unwrap() = zone(2);
+        let later = later + Duration::from_secs(3600);
+        assert_eq!(secondary.poll(later).unwrap(), Refresh::Transferred(Serial(2)));
+        assert_eq!(secondary.zone(later), Some(&zone(2)));
+    }
+
+    #[test]
+    fn zones_are_polled_and_transferred_in_their_class() {
+        let chaos = zone(1).records().iter().map(|rr| rr.with_class_and_ttl(Class::Chaos, rr.ttl().unwrap())).collect();
+        let chaos = Zone::from_records(chaos).unwrap();
+        let addr = primary(Arc::new(Mutex::new(chaos.clone())));
+        let start = Instant::now();
+        let mut secondary = SecondaryZone::new("example.com.".parse().unwrap(), addr, config(), start);
+        assert!(matches!(secondary.poll(start), Err(SecondaryError::QueryFailed(NotifyError::Rejected(Rcode::Refused)))));
+
+        let mut secondary = SecondaryZone::new("example.com.".parse().unwrap(), addr, config(), start).with_class(Class::Chaos);
+        assert_eq!(secondary.poll(start).unwrap(), Refresh::Transferred(Serial(1)));
+        assert_eq!(secondary.zone(start), Some(&chaos));
+    }
+
+    #[test]
+    fn signed_transfers_must_verify() {
+        let name: Name = "example.com.".parse().unwrap();
+        let timeout = Duration::from_millis(200);
+        let signed = signing_primary(Arc::new(Mutex::new(zone(1))), Some(key(b"secret")));
+        assert_eq!(transfer_zone(signed, &name, Class::Internet, Some(&key(b"secret")), timeout, deadline()).unwrap(), zone(1));
+
+        // The primary refuses a request signed with another secret, without signing its answer
+        assert!(matches!(transfer_zone(signed, &name, Class::Internet, Some(&key(b"guess")), timeout, deadline()),
+                         Err(SecondaryError::TransferUnauthenticated(TsigError::Signer(Rcode::BadSig)))));
+
+        // Unsigned transfers are not accepted once a key is configured
+        let unsigned = primary(Arc::new(Mutex::new(zone(1))));
+        assert!(matches!(transfer_zone(unsigned, &name, Class::Internet, Some(&key(b"secret")), timeout, deadline()),
+                         Err(SecondaryError::TransferUnauthenticated(TsigError::Unsigned))));
+    }
+
+    #[test]
+    fn slow_transfers_are_abandoned() {
+        // A primary which sends one record of an endless transfer at a time, each well within the
+        // read timeout
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let request = Message::decode(&read_tcp(&mut stream).unwrap()).unwrap();
+            let mut records = zone(1).records().to_vec().into_iter().cycle().filter(|rr| rr.rtype() != Type::SOA);
+            let mut first = Some(soa(1));
+            loop {
+                let mut response = Message::response(request.clone(), false);
+                response.answers.push(first.take().unwrap_or_else(|| records.next().unwrap()));
+                response.header.answer_count = 1;
+                if write_tcp(&mut stream, &response).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(500);
+        let result = transfer_zone(addr, &"example.com.".parse().unwrap(), Class::Internet, None, Duration::from_millis(200), deadline);
+        assert!(matches!(result, Err(SecondaryError::TransferTimedOut)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn transfers_of_another_zone_are_rejected() {
+        let addr = primary(Arc::new(Mutex::new(zone(1))));
+        let result = transfer_zone(addr, &"example.org.".parse().unwrap(), Class::Internet, None, Duration::from_millis(200), deadline());
+        assert!(matches!(result, Err(SecondaryError::TransferMalformed(_))));
+    }
+
+    #[test]
+    fn backs_off_and_expires_when_primary_is_unreachable() {
+        let data = Arc::new(Mutex::new(zone(1)));
+        let addr = primary(data);
+        let start = Instant::now();
+        let mut secondary = SecondaryZone::new("example.com.".parse().unwrap(), addr, config(), start);
+        secondary.poll(start).unwrap();
+
+        // Point the zone at a port nothing is listening on
+        let closed = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        secondary.primary = closed;
+        let mut now = start + Duration::from_secs(3600);
+        assert!(secondary.poll(now).is_err());
+        assert_eq!(secondary.next_check(), now + Duration::from_secs(600));
+        now += Duration::from_secs(600);
+        assert!(secondary.poll(now).is_err());
+        assert_eq!(secondary.next_check(), now + Duration::from_secs(1200));
+
+        assert!(secondary.zone(start + Duration::from_secs(86399)).is_some());
+        assert!(secondary.zone(start + Duration::from_secs(86400)).is_none());
+    }
+
+    #[test]
+    fn notify_from_primary_schedules_refresh() {
+        let data = Arc::new(Mutex::new(zone(1)));
+        let addr = primary(data);
+        let start = Instant::now();
+        let mut manager = SecondaryManager::new();
+        manager.add(SecondaryZone::new("example.com.".parse().unwrap(), addr, config(), start));
+        assert_eq!(manager.poll(start).len(), 1);
+        assert!(manager.poll(start).is_empty());
+
+        let name: Name = "example.com.".parse().unwrap();
+        assert_eq!(manager.notify(&name, Some(Serial(2)), "192.0.2.1:53".parse().unwrap()), Err(Rcode::Refused));
+        assert_eq!(manager.notify(&"example.org.".parse().unwrap(), None, addr), Err(Rcode::NotAuth));
+        assert_eq!(manager.notify(&name, Some(Serial(2)), addr), Ok(()));
+        assert!(manager.next_check().unwrap() <= Instant::now());
+        assert!(manager.zone(&name, start).is_some());
+    }
+}
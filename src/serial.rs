@@ -0,0 +1,165 @@
+//! Zone serial numbers and their sequence space arithmetic (RFC 1982).
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Add;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version number of a zone, as held in its `SOA` record.
+///
+/// Serials wrap around, so they are compared in sequence space: a serial is greater than another
+/// if it is less than 2^31 ahead of it. Serials exactly 2^31 apart are incomparable, and
+/// `partial_cmp` returns `None` for them.
+///
+/// ```
+/// # use martin::Serial;
+/// assert!(Serial(1) > Serial(0));
+/// assert!(Serial(0) > Serial(u32::MAX));
+/// assert_eq!(Serial(0).partial_cmp(&Serial(0x8000_0000)), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Serial(pub u32);
+
+/// The largest increment which keeps a serial comparable with its previous value.
+const MAX_INCREMENT: u32 = 0x7FFF_FFFF;
+
+impl Serial {
+    /// The raw value of the serial.
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+
+    /// The next serial in sequence.
+    pub fn increment(self) -> Serial {
+        self + 1
+    }
+
+    /// The next serial in the date-based `YYYYMMDDnn` scheme, using the UTC date of `now`.
+    ///
+    /// The first change on a date uses `nn` = `00`. Further changes increment the serial, which
+    /// spills over into the following date after 100 changes.
+    pub fn next_date(self, now: SystemTime) -> Serial {
+        let days = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86_400).unwrap_or(0);
+        let (year, month, day) = civil_from_days(days as i64);
+        let today = Serial((year as u32 * 10_000 + month * 100 + day) * 100);
+        self.advance_to(today)
+    }
+
+    /// The next serial in the scheme which uses the time in seconds since the UNIX epoch.
+    ///
+    /// If the clock has not moved past the current serial, the serial is incremented instead.
+    pub fn next_unixtime(self, now: SystemTime) -> Serial {
+        let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.advance_to(Serial(secs as u32))
+    }
+
+    /// Jumps to `target` if it is ahead of this serial and reachable in one step, otherwise
+    /// increments.
+    fn advance_to(self, target: Serial) -> Serial {
+        match target.partial_cmp(&self) {
+            Some(Ordering::Greater) => target,
+            _ => self.increment(),
+        }
+    }
+}
+
+/// Adds to the serial, wrapping around as described by RFC 1982.
+///
+/// # Panics
+///
+/// Panics if the increment is greater than 2^31 - 1, as the result would not compare greater.
+impl Add<u32> for Serial {
+    type Output = Serial;
+
+    fn add(self, n: u32) -> Serial {
+        assert!(n <= MAX_INCREMENT, "serial increments must be less than 2^31");
+        Serial(self.0.wrapping_add(n))
+    }
+}
+
+impl PartialOrd for Serial {
+    fn partial_cmp(&self, other: &Serial) -> Option<Ordering> {
+        match other.0.wrapping_sub(self.0) {
+            0 => Some(Ordering::Equal),
+            0x8000_0000 => None,
+            d if d < 0x8000_0000 => Some(Ordering::Less),
+            _ => Some(Ordering::Greater),
+        }
+    }
+}
+
+impl From<u32> for Serial {
+    fn from(value: u32) -> Serial {
+        Serial(value)
+    }
+}
+
+impl From<Serial> for u32 {
+    fn from(serial: Serial) -> u32 {
+        serial.0
+    }
+}
+
+impl fmt::Display for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Converts days since the UNIX epoch to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn comparisons_follow_sequence_space() {
+        assert!(Serial(2) > Serial(1));
+        assert!(Serial(1) < Serial(2));
+        assert!(Serial(5) < Serial(0x8000_0004));
+        assert!(Serial(5) > Serial(0x8000_0006));
+        assert!(Serial(0x8000_0000) > Serial(1));
+        assert_eq!(Serial(0x8000_0000).partial_cmp(&Serial(0)), None);
+        assert_eq!(Serial(u32::MAX).increment(), Serial(0));
+        assert!(Serial(u32::MAX).increment() > Serial(u32::MAX));
+    }
+
+    #[test]
+    #[should_panic]
+    fn increments_are_limited() {
+        let _ = Serial(0) + 0x8000_0000;
+    }
+
+    #[test]
+    fn date_serials() {
+        // 2023-11-14 22:13:20 UTC
+        let now = at(1_700_000_000);
+        assert_eq!(Serial(2023111300).next_date(now), Serial(2023111400));
+        assert_eq!(Serial(2023111400).next_date(now), Serial(2023111401));
+        assert_eq!(Serial(2023111499).next_date(now), Serial(2023111500));
+        assert_eq!(Serial(1).next_date(at(951_782_400)), Serial(2000022900));
+    }
+
+    #[test]
+    fn unixtime_serials() {
+        assert_eq!(Serial(5).next_unixtime(at(1_700_000_000)), Serial(1_700_000_000));
+        assert_eq!(Serial(1_700_000_000).next_unixtime(at(1_700_000_000)), Serial(1_700_000_001));
+    }
+}
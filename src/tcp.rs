@@ -0,0 +1,23 @@
+//! The framing of messages sent over TCP, where each is preceded by its 2 byte length.
+
+use crate::message::Message;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, Write};
+
+/// Writes a message with the 2 byte length prefix used over TCP.
+pub(crate) fn write_tcp<W: Write>(writer: &mut W, msg: &Message) -> io::Result<()> {
+    let data = msg.encode();
+    let mut framed = Vec::with_capacity(data.len() + 2);
+    framed.write_u16::<BigEndian>(data.len() as u16)?;
+    framed.extend(data);
+    writer.write_all(&framed)
+}
+
+/// Reads the data of a message with the 2 byte length prefix used over TCP.
+pub(crate) fn read_tcp<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let length = reader.read_u16::<BigEndian>()?;
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
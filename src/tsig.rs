@@ -0,0 +1,626 @@
+//! Transaction signatures for authenticating messages between servers (RFC 8945).
+
+use crate::header::Rcode;
+use crate::message::{last_record_offset, Message};
+use crate::names::Name;
+use crate::rr::{Class, ResourceRecord, Type};
+use byteorder::{BigEndian, WriteBytesExt};
+use hmac::{Hmac, Mac};
+use hmac::digest::KeyInit;
+use sha2::{Sha256, Sha384, Sha512};
+use std::io::Write;
+use thiserror::Error;
+
+/// The default permitted clock skew between signer and verifier, in seconds.
+pub const DEFAULT_FUDGE: u16 = 300;
+
+/// The MAC algorithms supported for signing and verification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// `hmac-sha256`
+    HmacSha256,
+    /// `hmac-sha384`
+    HmacSha384,
+    /// `hmac-sha512`
+    HmacSha512,
+}
+
+impl Algorithm {
+    /// The algorithm name, as carried in the `TSIG` record.
+    pub fn name(&self) -> Name {
+        let name = match self {
+            Algorithm::HmacSha256 => "hmac-sha256.",
+            Algorithm::HmacSha384 => "hmac-sha384.",
+            Algorithm::HmacSha512 => "hmac-sha512.",
+        };
+        name.parse().unwrap()
+    }
+
+    /// The length of an untruncated MAC, in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Algorithm::HmacSha256 => 32,
+            Algorithm::HmacSha384 => 48,
+            Algorithm::HmacSha512 => 64,
+        }
+    }
+
+    fn compute(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::HmacSha256 => hmac::<Hmac<Sha256>>(secret, data).finalize().into_bytes().to_vec(),
+            Algorithm::HmacSha384 => hmac::<Hmac<Sha384>>(secret, data).finalize().into_bytes().to_vec(),
+            Algorithm::HmacSha512 => hmac::<Hmac<Sha512>>(secret, data).finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn verify(&self, secret: &[u8], data: &[u8], mac: &[u8]) -> bool {
+        match self {
+            Algorithm::HmacSha256 => hmac::<Hmac<Sha256>>(secret, data).verify_truncated_left(mac).is_ok(),
+            Algorithm::HmacSha384 => hmac::<Hmac<Sha384>>(secret, data).verify_truncated_left(mac).is_ok(),
+            Algorithm::HmacSha512 => hmac::<Hmac<Sha512>>(secret, data).verify_truncated_left(mac).is_ok(),
+        }
+    }
+}
+
+fn hmac<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> M {
+    let mut mac = <M as KeyInit>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac
+}
+
+/// A named secret shared between two servers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    /// The name identifying the key to both parties.
+    pub name: Name,
+    /// The MAC algorithm used with this key.
+    pub algorithm: Algorithm,
+    /// The shared secret.
+    pub secret: Vec<u8>,
+    /// The permitted clock skew, in seconds.
+    pub fudge: u16,
+    /// The length to truncate MACs to when signing, which is also the shortest MAC accepted when
+    /// verifying. `None` uses the full algorithm output.
+    pub mac_size: Option<usize>,
+}
+
+impl Key {
+    /// Create a `Key` producing untruncated MACs with the default fudge.
+    pub fn new(name: Name, algorithm: Algorithm, secret: Vec<u8>) -> Key {
+        Key {
+            name,
+            algorithm,
+            secret,
+            fudge: DEFAULT_FUDGE,
+            mac_size: None,
+        }
+    }
+
+    fn mac_size(&self) -> usize {
+        self.mac_size.unwrap_or_else(|| self.algorithm.output_len())
+    }
+}
+
+/// Reasons for a signed message failing verification.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum TsigError {
+    #[error("the message is not signed")]
+    Unsigned,
+
+    #[error("the TSIG record is malformed: {0}")]
+    FormatError(&'static str),
+
+    #[error("the message was signed with an unknown key or algorithm")]
+    BadKey,
+
+    #[error("the message signature is invalid")]
+    BadSig,
+
+    #[error("the message was signed outside the permitted time window")]
+    BadTime,
+
+    #[error("the message signature was truncated below the permitted length")]
+    BadTrunc,
+
+    #[error("the signer reported an error: {0:?}")]
+    Signer(Rcode),
+}
+
+impl TsigError {
+    /// The response code which reports this error to the peer.
+    ///
+    /// The extended codes do not fit in the header, so they are carried in the `error` field of
+    /// the `TSIG` record of a response whose header carries `NOTAUTH`; see `error_response`.
+    pub fn rcode(&self) -> Rcode {
+        match self {
+            TsigError::Unsigned | TsigError::FormatError(_) => Rcode::FormatError,
+            TsigError::BadKey => Rcode::BadKey,
+            TsigError::BadSig => Rcode::BadSig,
+            TsigError::BadTime => Rcode::BadTime,
+            TsigError::BadTrunc => Rcode::BadTrunc,
+            TsigError::Signer(rcode) => *rcode,
+        }
+    }
+}
+
+/// The most unsigned messages allowed in a row in a multi-message response.
+pub const MAX_UNSIGNED_MESSAGES: usize = 99;
+
+/// Signs a request, returning the MAC needed to verify the response.
+///
+/// `now` is the current time in seconds since the UNIX epoch.
+pub fn sign_request(msg: &mut Message, key: &Key, now: u64) -> Vec<u8> {
+    sign(msg, key, Vec::new(), true, now, 0, Vec::new())
+}
+
+/// Verifies a signed request as it was received in `wire`, returning the key it was signed with
+/// and its MAC.
+///
+/// The MAC covers the message exactly as the signer encoded it, compressed names and all, so it
+/// is checked against the received bytes rather than a re-encoding.
+pub fn verify_request<'a>(wire: &[u8], keys: &'a [Key], now: u64) -> Result<(&'a Key, Vec<u8>), TsigError> {
+    let msg = Message::decode(wire).map_err(|_| TsigError::FormatError("malformed message"))?;
+    let (name, algorithm) = match tsig_record(&msg)? {
+        ResourceRecord::TSIG { name, algorithm, .. } => (name, algorithm),
+        _ => unreachable!(),
+    };
+    let key = find_key(keys, name, algorithm).ok_or(TsigError::BadKey)?;
+    let mac = verify(&msg, wire, key, None, &[], true, now)?;
+    Ok((key, mac))
+}
+
+/// Builds the response reporting a failure to verify `request` to its sender.
+///
+/// The header carries `NOTAUTH`, and a `TSIG` record the extended error. Responses to bad keys
+/// and signatures are unsigned, as the sender cannot be trusted; responses to bad times and
+/// truncation are signed with the request's key, found among `keys`. Other errors are reported
+/// as `FORMERR` without a `TSIG` record.
+pub fn error_response(request: &Message, keys: &[Key], error: &TsigError, now: u64) -> Message {
+    let mut response = Message::response(request.clone(), false);
+    let tsig = match (error, tsig_record(request)) {
+        (TsigError::BadKey | TsigError::BadSig | TsigError::BadTime | TsigError::BadTrunc, Ok(tsig)) => tsig,
+        _ => {
+            response.header.rcode = Rcode::FormatError;
+            return response;
+        }
+    };
+    let (name, algorithm, time_signed, fudge, request_mac) = match tsig {
+        ResourceRecord::TSIG { name, algorithm, time_signed, fudge, mac, .. } => (name, algorithm, *time_signed, *fudge, mac),
+        _ => unreachable!(),
+    };
+    response.header.rcode = Rcode::NotAuth;
+    let code: u8 = error.rcode().into();
+    match (error, find_key(keys, name, algorithm)) {
+        (TsigError::BadTime, Some(key)) => {
+            // Signed at the request's time, so that the sender accepts it, with ours alongside
+            let mut other = Vec::new();
+            other.write_u48::<BigEndian>(now).unwrap();
+            sign(&mut response, key, digest_prefix(Some(request_mac), &[]), true, time_signed, code as u16, other);
+        }
+        (TsigError::BadTrunc, Some(key)) => {
+            sign(&mut response, key, digest_prefix(Some(request_mac), &[]), true, now, code as u16, Vec::new());
+        }
+        _ => {
+            response.additionals.push(ResourceRecord::TSIG {
+                name: name.clone(),
+                algorithm: algorithm.clone(),
+                time_signed: now,
+                fudge,
+                mac: Vec::new(),
+                original_id: request.id(),
+                error: code as u16,
+                other: Vec::new(),
+            });
+            response.header.additional_count += 1;
+        }
+    }
+    response
+}
+
+/// Signs the messages of a response, which may be a multi-message stream such as `AXFR`.
+///
+/// The first message is signed over the request MAC and all TSIG variables; each following
+/// message is signed over the preceding MAC, any messages left unsigned since, and the timers.
+pub struct ResponseSigner<'a> {
+    key: &'a Key,
+    prior_mac: Vec<u8>,
+    first: bool,
+    unsigned: Vec<u8>,
+}
+
+impl<'a> ResponseSigner<'a> {
+    /// Create a `ResponseSigner` for the request which was signed with `request_mac`.
+    pub fn new(key: &'a Key, request_mac: &[u8]) -> ResponseSigner<'a> {
+        ResponseSigner {
+            key,
+            prior_mac: request_mac.to_vec(),
+            first: true,
+            unsigned: Vec::new(),
+        }
+    }
+
+    /// Appends a `TSIG` record to the next message in the response.
+    pub fn sign(&mut self, msg: &mut Message, now: u64) {
+        let prefix = digest_prefix(Some(&self.prior_mac), &self.unsigned);
+        self.prior_mac = sign(msg, self.key, prefix, self.first, now, 0, Vec::new());
+        self.first = false;
+        self.unsigned.clear();
+    }
+
+    /// Sends the next message in the response, which must not be the first or last, without a
+    /// `TSIG` record. The next message signed covers it.
+    pub fn skip(&mut self, msg: &Message) {
+        self.unsigned.extend(msg.encode());
+    }
+}
+
+/// Verifies the messages of a response, which may be a multi-message stream such as `AXFR`.
+///
+/// The first message must carry a `TSIG` record. Up to `MAX_UNSIGNED_MESSAGES` messages in a row
+/// may then go without one, and are covered by the next signed message; `finish` checks that the
+/// last message was signed.
+pub struct ResponseVerifier<'a> {
+    key: &'a Key,
+    prior_mac: Vec<u8>,
+    first: bool,
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl<'a> ResponseVerifier<'a> {
+    /// Create a `ResponseVerifier` for the request which was signed with `request_mac`.
+    pub fn new(key: &'a Key, request_mac: &[u8]) -> ResponseVerifier<'a> {
+        ResponseVerifier {
+            key,
+            prior_mac: request_mac.to_vec(),
+            first: true,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    /// Verifies the next message in the response, as it was received in `wire`.
+    pub fn verify(&mut self, wire: &[u8], now: u64) -> Result<(), TsigError> {
+        let msg = Message::decode(wire).map_err(|_| TsigError::FormatError("malformed message"))?;
+        match tsig_record(&msg) {
+            Err(TsigError::Unsigned) if !self.first && self.unsigned_count < MAX_UNSIGNED_MESSAGES => {
+                self.unsigned.extend_from_slice(wire);
+                self.unsigned_count += 1;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+        self.prior_mac = verify(&msg, wire, self.key, Some(&self.prior_mac), &self.unsigned, self.first, now)?;
+        self.first = false;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+        Ok(())
+    }
+
+    /// Checks that the response ended with a signed message.
+    pub fn finish(&self) -> Result<(), TsigError> {
+        if self.first || self.unsigned_count > 0 {
+            return Err(TsigError::Unsigned);
+        }
+        Ok(())
+    }
+}
+
+fn find_key<'a>(keys: &'a [Key], name: &Name, algorithm: &Name) -> Option<&'a Key> {
+    keys.iter().find(|k| k.name.eq_ignore_case(name) && k.algorithm.name().eq_ignore_case(algorithm))
+}
+
+/// The start of a digest: the MAC of the previous message, if any, and the messages sent
+/// without one since.
+fn digest_prefix(prior_mac: Option<&[u8]>, unsigned: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Some(mac) = prior_mac {
+        data.write_u16::<BigEndian>(mac.len() as u16).unwrap();
+        data.write_all(mac).unwrap();
+    }
+    data.extend_from_slice(unsigned);
+    data
+}
+
+/// Appends a `TSIG` record to `msg`, with a MAC over `data` followed by the message and the TSIG
+/// variables.
+fn sign(msg: &mut Message,
+        key: &Key,
+        mut data: Vec<u8>,
+        all_variables: bool,
+        time_signed: u64,
+        error: u16,
+        other: Vec<u8>)
+        -> Vec<u8> {
+    // Nothing is compressed, so the message is sent as encoded here, followed by the TSIG record
+    data.extend(msg.encode());
+    digest_variables(&mut data, &key.name, key.algorithm, time_signed, key.fudge, error, &other, all_variables);
+    let mut mac = key.algorithm.compute(&key.secret, &data);
+    mac.truncate(key.mac_size());
+    msg.additionals.push(ResourceRecord::TSIG {
+        name: key.name.clone(),
+        algorithm: key.algorithm.name(),
+        time_signed,
+        fudge: key.fudge,
+        mac: mac.clone(),
+        original_id: msg.id(),
+        error,
+        other,
+    });
+    msg.header.additional_count += 1;
+    mac
+}
+
+/// Verifies the signed message `msg`, which was received in `wire` after the `unsigned`
+/// messages, following RFC 8945 section 5.2.
+fn verify(msg: &Message,
+          wire: &[u8],
+          key: &Key,
+          prior_mac: Option<&[u8]>,
+          unsigned: &[u8],
+          all_variables: bool,
+          now: u64)
+          -> Result<Vec<u8>, TsigError> {
+    let (name, algorithm, time_signed, fudge, mac, original_id, error, other) = match tsig_record(msg)? {
+        ResourceRecord::TSIG { name, algorithm, time_signed, fudge, mac, original_id, error, other } =>
+            (name, algorithm, *time_signed, *fudge, mac, *original_id, *error, other),
+        _ => unreachable!(),
+    };
+    if !key.name.eq_ignore_case(name) || !key.algorithm.name().eq_ignore_case(algorithm) {
+        return Err(TsigError::BadKey);
+    }
+    // Errors about our own request come back unsigned when the peer could not check it
+    if mac.is_empty() && error != 0 {
+        return Err(TsigError::Signer(Rcode::from(error as u8)));
+    }
+    let full_len = key.algorithm.output_len();
+    if mac.len() > full_len || mac.len() < std::cmp::max(10, full_len / 2) {
+        return Err(TsigError::FormatError("invalid MAC size"));
+    }
+
+    // The digest covers the message as received, without its TSIG record and with its original ID
+    let offset = last_record_offset(wire).ok_or(TsigError::FormatError("malformed message"))?;
+    let mut data = digest_prefix(prior_mac, unsigned);
+    data.write_u16::<BigEndian>(original_id).unwrap();
+    data.extend_from_slice(&wire[2..10]);
+    data.write_u16::<BigEndian>(msg.header.additional_count - 1).unwrap();
+    data.extend_from_slice(&wire[12..offset]);
+    digest_variables(&mut data, name, key.algorithm, time_signed, fudge, error, other, all_variables);
+    if !key.algorithm.verify(&key.secret, &data, mac) {
+        return Err(TsigError::BadSig);
+    }
+    if mac.len() < key.mac_size() {
+        return Err(TsigError::BadTrunc);
+    }
+    if now.abs_diff(time_signed) > fudge as u64 {
+        return Err(TsigError::BadTime);
+    }
+    if error != 0 {
+        return Err(TsigError::Signer(Rcode::from(error as u8)));
+    }
+    Ok(mac.clone())
+}
+
+/// Finds the `TSIG` record, which must be the last record of the message.
+fn tsig_record(msg: &Message) -> Result<&ResourceRecord, TsigError> {
+    let position = msg.additionals.iter().position(|rr| rr.rtype() == Type::TSIG);
+    match position {
+        None => Err(TsigError::Unsigned),
+        Some(p) if p + 1 != msg.additionals.len() => Err(TsigError::FormatError("TSIG is not the last record")),
+        Some(p) => Ok(&msg.additionals[p]),
+    }
+}
+
+/// Appends the TSIG variables covered by the MAC to `data`, which holds the message.
+#[allow(clippy::too_many_arguments)]
+fn digest_variables(data: &mut Vec<u8>,
+                    key_name: &Name,
+                    algorithm: Algorithm,
+                    time_signed: u64,
+                    fudge: u16,
+                    error: u16,
+                    other: &[u8],
+                    all_variables: bool) {
+    if all_variables {
+        data.write_all(&key_name.to_lowercase().name).unwrap();
+        data.write_u16::<BigEndian>(Class::Any.into()).unwrap();
+        data.write_u32::<BigEndian>(0).unwrap();
+        data.write_all(&algorithm.name().name).unwrap();
+    }
+    data.write_u48::<BigEndian>(time_signed).unwrap();
+    data.write_u16::<BigEndian>(fudge).unwrap();
+    if all_variables {
+        data.write_u16::<BigEndian>(error).unwrap();
+        data.write_u16::<BigEndian>(other.len() as u16).unwrap();
+        data.write_all(other).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::{QType, Question};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn key() -> Key {
+        Key::new("transfer.example.com.".parse().unwrap(), Algorithm::HmacSha256, b"secret".to_vec())
+    }
+
+    fn query() -> Message {
+        Message::query(42, false, Question::new("example.com.", QType::ByType(Type::SOA)).unwrap())
+    }
+
+    #[test]
+    fn signed_request_verifies_after_encoding() {
+        let key = key();
+        let mut msg = query();
+        let mac = sign_request(&mut msg, &key, NOW);
+        let (found, verified_mac) = verify_request(&msg.encode(), std::slice::from_ref(&key), NOW + 10).unwrap();
+        assert_eq!(found, &key);
+        assert_eq!(verified_mac, mac);
+    }
+
+    #[test]
+    fn compressed_messages_signed_elsewhere_verify() {
+        // A response for example.com. with compressed names, signed by hickory-dns 0.24 with the
+        // same key at NOW
+        let wire = [
+            0x12, 0x34, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x07, 0x65, 0x78, 0x61,
+            0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x06, 0x00, 0x01, 0xc0, 0x0c, 0x00,
+            0x06, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x27, 0x03, 0x6e, 0x73, 0x31, 0xc0, 0x0c, 0x0a,
+            0x68, 0x6f, 0x73, 0x74, 0x6d, 0x61, 0x73, 0x74, 0x65, 0x72, 0xc0, 0x0c, 0x00, 0x00, 0x00, 0x0a,
+            0x00, 0x00, 0x0e, 0x10, 0x00, 0x00, 0x02, 0x58, 0x00, 0x01, 0x51, 0x80, 0x00, 0x00, 0x01, 0x2c,
+            0xc0, 0x0c, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0, 0x29, 0x08, 0x74,
+            0x72, 0x61, 0x6e, 0x73, 0x66, 0x65, 0x72, 0xc0, 0x0c, 0x00, 0xfa, 0x00, 0xff, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x3d, 0x0b, 0x68, 0x6d, 0x61, 0x63, 0x2d, 0x73, 0x68, 0x61, 0x32, 0x35, 0x36, 0x00,
+            0x00, 0x00, 0x65, 0x53, 0xf1, 0x00, 0x01, 0x2c, 0x00, 0x20, 0x9a, 0x82, 0xf4, 0x31, 0x03, 0x12,
+            0x93, 0xf1, 0x86, 0xcd, 0xb6, 0xe6, 0xc8, 0x5c, 0xfc, 0x6e, 0x85, 0x2d, 0xb6, 0xa3, 0xe9, 0xef,
+            0x42, 0xa7, 0x74, 0xda, 0x3e, 0x93, 0x8d, 0xa0, 0x02, 0x8f, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let key = key();
+        assert!(verify_request(&wire, std::slice::from_ref(&key), NOW).is_ok());
+
+        // Re-encoding drops the compression, and with it the signature
+        let reencoded = Message::decode(&wire).unwrap().encode();
+        assert_ne!(reencoded.len(), wire.len());
+        assert_eq!(verify_request(&reencoded, &[key], NOW), Err(TsigError::BadSig));
+    }
+
+    #[test]
+    fn verification_failures_map_to_extended_rcodes() {
+        let key = key();
+        let mut msg = query();
+        sign_request(&mut msg, &key, NOW);
+
+        let mut tampered = msg.clone();
+        tampered.header.recursion_desired = true;
+        let err = verify_request(&tampered.encode(), std::slice::from_ref(&key), NOW).unwrap_err();
+        assert_eq!(err.rcode(), Rcode::BadSig);
+
+        let other = Key::new("other.example.com.".parse().unwrap(), Algorithm::HmacSha256, b"secret".to_vec());
+        let err = verify_request(&msg.encode(), &[other], NOW).unwrap_err();
+        assert_eq!(err.rcode(), Rcode::BadKey);
+
+        let err = verify_request(&msg.encode(), std::slice::from_ref(&key), NOW + 301).unwrap_err();
+        assert_eq!(err.rcode(), Rcode::BadTime);
+
+        assert_eq!(verify_request(&query().encode(), &[key], NOW), Err(TsigError::Unsigned));
+    }
+
+    #[test]
+    fn error_responses_carry_extended_rcodes_in_tsig() {
+        let key = key();
+        let mut request = query();
+        let request_mac = sign_request(&mut request, &key, NOW);
+
+        // A bad time is reported in a response signed at the request's time
+        let response = error_response(&request, std::slice::from_ref(&key), &TsigError::BadTime, NOW + 400);
+        let wire = response.encode();
+        let decoded = Message::decode(&wire).unwrap();
+        assert_eq!(decoded.rcode(), Rcode::NotAuth);
+        match decoded.additionals.last().unwrap() {
+            ResourceRecord::TSIG { error, other, time_signed, .. } => {
+                assert_eq!(*error, 18);
+                assert_eq!(*time_signed, NOW);
+                assert_eq!(other, &(NOW + 400).to_be_bytes()[2..]);
+            }
+            rr => panic!("unexpected {rr}"),
+        }
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        assert_eq!(verifier.verify(&wire, NOW), Err(TsigError::Signer(Rcode::BadTime)));
+
+        // A bad key is reported without a MAC
+        let response = error_response(&request, &[], &TsigError::BadKey, NOW);
+        let decoded = Message::decode(&response.encode()).unwrap();
+        assert_eq!(decoded.rcode(), Rcode::NotAuth);
+        assert!(matches!(decoded.additionals.last(), Some(ResourceRecord::TSIG { error: 17, mac, .. }) if mac.is_empty()));
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        assert_eq!(verifier.verify(&response.encode(), NOW), Err(TsigError::Signer(Rcode::BadKey)));
+
+        let response = error_response(&query(), &[key], &TsigError::Unsigned, NOW);
+        assert_eq!(response.rcode(), Rcode::FormatError);
+        assert!(response.additionals.is_empty());
+    }
+
+    #[test]
+    fn truncated_macs_follow_local_policy() {
+        let mut truncating = key();
+        truncating.algorithm = Algorithm::HmacSha512;
+        truncating.mac_size = Some(32);
+        let mut msg = query();
+        assert_eq!(sign_request(&mut msg, &truncating, NOW).len(), 32);
+        assert!(verify_request(&msg.encode(), std::slice::from_ref(&truncating), NOW).is_ok());
+
+        // Truncation is checked before the time
+        let mut strict = truncating.clone();
+        strict.mac_size = None;
+        assert_eq!(verify_request(&msg.encode(), &[strict], NOW + 301), Err(TsigError::BadTrunc));
+    }
+
+    #[test]
+    fn multi_message_responses_chain_macs() {
+        let key = key();
+        let mut request = query();
+        let request_mac = sign_request(&mut request, &key, NOW);
+
+        let mut signer = ResponseSigner::new(&key, &request_mac);
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let mut response = Message::response(query(), false);
+            signer.sign(&mut response, NOW);
+            responses.push(response.encode());
+        }
+
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        for response in responses.iter() {
+            verifier.verify(response, NOW).unwrap();
+        }
+        verifier.finish().unwrap();
+
+        // Skipping a message in the stream breaks the chain
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        verifier.verify(&responses[0], NOW).unwrap();
+        assert_eq!(verifier.verify(&responses[2], NOW), Err(TsigError::BadSig));
+    }
+
+    #[test]
+    fn unsigned_messages_are_covered_by_the_next_signed_one() {
+        let key = key();
+        let mut request = query();
+        let request_mac = sign_request(&mut request, &key, NOW);
+
+        let mut signer = ResponseSigner::new(&key, &request_mac);
+        let mut responses = Vec::new();
+        for i in 0..4 {
+            let mut response = Message::response(query(), false);
+            if i == 1 || i == 2 {
+                signer.skip(&response);
+            } else {
+                signer.sign(&mut response, NOW);
+            }
+            responses.push(response.encode());
+        }
+
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        for response in responses.iter() {
+            verifier.verify(response, NOW).unwrap();
+        }
+        verifier.finish().unwrap();
+
+        // The first and last messages must be signed
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        assert_eq!(verifier.verify(&responses[1], NOW), Err(TsigError::Unsigned));
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        verifier.verify(&responses[0], NOW).unwrap();
+        verifier.verify(&responses[1], NOW).unwrap();
+        assert_eq!(verifier.finish(), Err(TsigError::Unsigned));
+
+        // Dropping an unsigned message is noticed by the next signed one
+        let mut verifier = ResponseVerifier::new(&key, &request_mac);
+        verifier.verify(&responses[0], NOW).unwrap();
+        verifier.verify(&responses[1], NOW).unwrap();
+        assert_eq!(verifier.verify(&responses[3], NOW), Err(TsigError::BadSig));
+    }
+}
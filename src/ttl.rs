@@ -0,0 +1,176 @@
+//! The "time to live" of resource records.
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The time, in seconds, for which a resource record may be cached.
+///
+/// RFC 2181 §8 limits TTLs to 2^31 - 1 seconds; received values with the most significant bit
+/// set are treated as zero.
+///
+/// TTLs can be parsed from plain seconds or the unit syntax used by BIND:
+///
+/// ```
+/// # use martin::Ttl;
+/// let ttl: Ttl = "1h30m".parse().unwrap();
+/// assert_eq!(ttl.as_secs(), 5400);
+/// assert_eq!("2w".parse::<Ttl>().unwrap().as_secs(), 1_209_600);
+/// assert_eq!(Ttl::from_secs(0x8000_0000), Ttl::ZERO);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ttl(u32);
+
+impl Ttl {
+    /// A TTL of zero: the record must not be cached.
+    pub const ZERO: Ttl = Ttl(0);
+    /// The largest TTL allowed, 2^31 - 1 seconds.
+    pub const MAX: Ttl = Ttl(0x7FFF_FFFF);
+
+    /// Create a `Ttl` from a number of seconds. Values above `Ttl::MAX` are treated as zero.
+    pub const fn from_secs(secs: u32) -> Ttl {
+        if secs > Ttl::MAX.0 {
+            Ttl::ZERO
+        } else {
+            Ttl(secs)
+        }
+    }
+
+    /// The number of seconds in this TTL.
+    pub const fn as_secs(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for Ttl {
+    fn from(secs: u32) -> Ttl {
+        Ttl::from_secs(secs)
+    }
+}
+
+impl From<Ttl> for u32 {
+    fn from(ttl: Ttl) -> u32 {
+        ttl.0
+    }
+}
+
+impl From<Ttl> for Duration {
+    fn from(ttl: Ttl) -> Duration {
+        Duration::from_secs(ttl.0 as u64)
+    }
+}
+
+/// Converts a `Duration` to whole seconds, saturating at `Ttl::MAX`.
+impl From<Duration> for Ttl {
+    fn from(duration: Duration) -> Ttl {
+        Ttl(duration.as_secs().min(Ttl::MAX.0 as u64) as u32)
+    }
+}
+
+impl fmt::Display for Ttl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An error returned when parsing a TTL
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TtlParseError {
+    /// The string was empty
+    Empty,
+    /// A unit was not preceded by a number
+    MissingNumber,
+    /// Valid units are 's', 'm', 'h', 'd', and 'w'
+    InvalidUnit(char),
+    /// The TTL is larger than 2^31 - 1 seconds
+    TooLarge,
+}
+
+impl fmt::Display for TtlParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::TtlParseError::*;
+        match *self {
+            Empty => write!(fmt, "TTL must not be empty"),
+            MissingNumber => write!(fmt, "TTL units must follow a number"),
+            InvalidUnit(c) => write!(fmt, "Valid TTL units are s, m, h, d, and w. Found: '{}'", c),
+            TooLarge => write!(fmt, "TTL must be less than 2^31 seconds"),
+        }
+    }
+}
+
+impl error::Error for TtlParseError {}
+
+impl FromStr for Ttl {
+    type Err = TtlParseError;
+    fn from_str(s: &str) -> Result<Ttl, TtlParseError> {
+        use self::TtlParseError::*;
+        if s.is_empty() {
+            return Err(Empty);
+        }
+        let mut total: u64 = 0;
+        let mut number: Option<u64> = None;
+        for c in s.chars() {
+            let multiplier = match c.to_ascii_lowercase() {
+                '0'..='9' => {
+                    let digit = c.to_digit(10).unwrap() as u64;
+                    let n = number.unwrap_or(0) * 10 + digit;
+                    if n > Ttl::MAX.0 as u64 {
+                        return Err(TooLarge);
+                    }
+                    number = Some(n);
+                    continue;
+                }
+                's' => 1,
+                'm' => 60,
+                'h' => 3_600,
+                'd' => 86_400,
+                'w' => 604_800,
+                c => return Err(InvalidUnit(c)),
+            };
+            total += number.take().ok_or(MissingNumber)? * multiplier;
+            if total > Ttl::MAX.0 as u64 {
+                return Err(TooLarge);
+            }
+        }
+        // A trailing number without a unit counts seconds
+        total += number.unwrap_or(0);
+        if total > Ttl::MAX.0 as u64 {
+            return Err(TooLarge);
+        }
+        Ok(Ttl(total as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_units() {
+        assert_eq!("3600".parse(), Ok(Ttl(3600)));
+        assert_eq!("1h30m".parse(), Ok(Ttl(5400)));
+        assert_eq!("1W2D".parse(), Ok(Ttl(777_600)));
+        assert_eq!("1d12h30m15s".parse(), Ok(Ttl(131_415)));
+        assert_eq!("1m30".parse(), Ok(Ttl(90)));
+        assert_eq!("".parse::<Ttl>(), Err(TtlParseError::Empty));
+        assert_eq!("h".parse::<Ttl>(), Err(TtlParseError::MissingNumber));
+        assert_eq!("5y".parse::<Ttl>(), Err(TtlParseError::InvalidUnit('y')));
+        assert_eq!("4000w".parse::<Ttl>(), Err(TtlParseError::TooLarge));
+        assert_eq!("2147483648".parse::<Ttl>(), Err(TtlParseError::TooLarge));
+    }
+
+    #[test]
+    fn out_of_range_values_are_zero() {
+        assert_eq!(Ttl::from_secs(0x7FFF_FFFF), Ttl::MAX);
+        assert_eq!(Ttl::from_secs(0x8000_0000), Ttl::ZERO);
+        assert_eq!(Ttl::from(u32::MAX), Ttl::ZERO);
+    }
+
+    #[test]
+    fn duration_conversions() {
+        assert_eq!(Duration::from(Ttl(90)), Duration::from_secs(90));
+        assert_eq!(Ttl::from(Duration::from_millis(90_500)), Ttl(90));
+        assert_eq!(Ttl::from(Duration::from_secs(u64::MAX)), Ttl::MAX);
+    }
+}
@@ -0,0 +1,345 @@
+//! Dynamic updates to a zone, as described by RFC 2136.
+
+use crate::header::{Header, Opcode, Rcode};
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::{QType, Question};
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::ttl::Ttl;
+use crate::zone::Zone;
+use thiserror::Error;
+
+/// The `TYPE` value used by update RRs to match every type at a name.
+const TYPE_ANY: Type = Type::Unknown { value: 255 };
+
+/// A condition which must hold in the zone before an update is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prerequisite {
+    /// At least one RR with the given name and type exists.
+    RRsetExists {
+        /// The owner name of the RRset.
+        name: Name,
+        /// The type of the RRset.
+        rtype: Type,
+    },
+    /// An RRset exists which contains exactly these records.
+    ///
+    /// All records must share the same name and type; TTLs are ignored.
+    RRsetEquals {
+        /// The complete contents of the RRset.
+        records: Vec<ResourceRecord>,
+    },
+    /// No RR with the given name and type exists.
+    RRsetDoesNotExist {
+        /// The owner name of the RRset.
+        name: Name,
+        /// The type of the RRset.
+        rtype: Type,
+    },
+    /// At least one RR of any type is owned by the name.
+    NameInUse {
+        /// The name which must be in use.
+        name: Name,
+    },
+    /// No RR of any type is owned by the name.
+    NameNotInUse {
+        /// The name which must not be in use.
+        name: Name,
+    },
+}
+
+/// A change to make to the zone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateOp {
+    /// Adds a record to an RRset, replacing any record with identical data.
+    Add(ResourceRecord),
+    /// Deletes the RRset with the given name and type.
+    DeleteRRset {
+        /// The owner name of the RRset.
+        name: Name,
+        /// The type of the RRset.
+        rtype: Type,
+    },
+    /// Deletes all RRsets owned by the name.
+    DeleteAll {
+        /// The owner name of the RRsets.
+        name: Name,
+    },
+    /// Deletes a single record from an RRset. The TTL is ignored.
+    DeleteRecord(ResourceRecord),
+}
+
+/// A typed representation of a dynamic update message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    /// A 16 bit identifier assigned by the program.
+    pub id: u16,
+    /// The zone to which the update applies.
+    pub zone: Name,
+    /// The class of the zone.
+    pub class: Class,
+    /// Conditions which must all hold before any update is made.
+    pub prerequisites: Vec<Prerequisite>,
+    /// Changes to make to the zone, in order.
+    pub updates: Vec<UpdateOp>,
+    /// Resource records holding additional information
+    pub additionals: Vec<ResourceRecord>,
+}
+
+/// Reasons for an update being rejected.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum UpdateError {
+    #[error("the message is not a valid update: {0}")]
+    FormatError(&'static str),
+
+    #[error("the server is not authoritative for zone {0}")]
+    NotAuth(Name),
+
+    #[error("{0} is not within the zone")]
+    NotZone(Name),
+
+    #[error("prerequisite failed: {0} is in use")]
+    NameInUse(Name),
+
+    #[error("prerequisite failed: {0} is not in use")]
+    NameNotInUse(Name),
+
+    #[error("prerequisite failed: RRset {0} {1} exists")]
+    RRsetExists(Name, Type),
+
+    #[error("prerequisite failed: RRset {0} {1} does not exist")]
+    RRsetDoesNotExist(Name, Type),
+}
+
+impl UpdateError {
+    /// The response code which reports this error to the client.
+    pub fn rcode(&self) -> Rcode {
+        match self {
+            UpdateError::FormatError(_) => Rcode::FormatError,
+            UpdateError::NotAuth(_) => Rcode::NotAuth,
+            UpdateError::NotZone(_) => Rcode::NotZone,
+            UpdateError::NameInUse(_) => Rcode::YXDomain,
+            UpdateError::NameNotInUse(_) => Rcode::NameError,
+            UpdateError::RRsetExists(..) => Rcode::YXRRSet,
+            UpdateError::RRsetDoesNotExist(..) => Rcode::NXRRSet,
+        }
+    }
+}
+
+impl Update {
+    /// Create an empty `Update` for a zone in the Internet class.
+    pub fn new(id: u16, zone: Name) -> Update {
+        Update {
+            id,
+            zone,
+            class: Class::Internet,
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            additionals: Vec::new(),
+        }
+    }
+
+    /// Encodes the update into a `Message`, ready to be sent to the primary server.
+    pub fn to_message(&self) -> Message {
+        let mut prerequisites = Vec::new();
+        for p in self.prerequisites.iter() {
+            match p {
+                Prerequisite::RRsetExists { name, rtype } =>
+                    prerequisites.push(empty_rr(name, *rtype, Class::Any)),
+                Prerequisite::RRsetEquals { records } =>
+                    prerequisites.extend(records.iter().map(|rr| rr.with_class_and_ttl(self.class, Ttl::ZERO))),
+                Prerequisite::RRsetDoesNotExist { name, rtype } =>
+                    prerequisites.push(empty_rr(name, *rtype, Class::None)),
+                Prerequisite::NameInUse { name } =>
+                    prerequisites.push(empty_rr(name, TYPE_ANY, Class::Any)),
+                Prerequisite::NameNotInUse { name } =>
+                    prerequisites.push(empty_rr(name, TYPE_ANY, Class::None)),
+            }
+        }
+        let updates: Vec<ResourceRecord> = self.updates.iter()
+            .map(|u| match u {
+                UpdateOp::Add(rr) => rr.with_class_and_ttl(self.class, rr.ttl().unwrap_or(Ttl::ZERO)),
+                UpdateOp::DeleteRRset { name, rtype } => empty_rr(name, *rtype, Class::Any),
+                UpdateOp::DeleteAll { name } => empty_rr(name, TYPE_ANY, Class::Any),
+                UpdateOp::DeleteRecord(rr) => rr.with_class_and_ttl(Class::None, Ttl::ZERO),
+            })
+            .collect();
+
+        let mut header = Header::query(self.id, Opcode::Update, false, 1);
+        header.answer_count = prerequisites.len() as u16;
+        header.ns_count = updates.len() as u16;
+        header.additional_count = self.additionals.len() as u16;
+        Message {
+            header,
+            questions: vec![Question {
+                qname: self.zone.clone(),
+                qtype: QType::ByType(Type::SOA),
+                qclass: self.class,
+            }],
+            answers: prerequisites,
+            authorities: updates,
+            additionals: self.additionals.clone(),
+        }
+    }
+
+    /// Interprets a received `Message` as an update.
+    pub fn from_message(msg: &Message) -> Result<Update, UpdateError> {
+        if msg.opcode() != Opcode::Update {
+            return Err(UpdateError::FormatError("opcode is not UPDATE"));
+        }
+        let zone = match msg.questions.as_slice() {
+            [q] if q.qtype == QType::ByType(Type::SOA) => q,
+            _ => return Err(UpdateError::FormatError("zone section must hold a single SOA question")),
+        };
+        let class = zone.qclass;
+
+        let mut prerequisites: Vec<Prerequisite> = Vec::new();
+        for rr in msg.answers.iter() {
+            let name = rr.name().ok_or(UpdateError::FormatError("unexpected OPT record"))?.clone();
+            let rtype = rr.rtype();
+            let empty = matches!(rr, ResourceRecord::Unknown { data, .. } if data.is_empty());
+            if rr.ttl() != Some(Ttl::ZERO) {
+                return Err(UpdateError::FormatError("prerequisite TTL must be zero"));
+            }
+            let prerequisite = match rr.class() {
+                Some(Class::Any) if empty && rtype == TYPE_ANY => Prerequisite::NameInUse { name },
+                Some(Class::Any) if empty => Prerequisite::RRsetExists { name, rtype },
+                Some(Class::None) if empty && rtype == TYPE_ANY => Prerequisite::NameNotInUse { name },
+                Some(Class::None) if empty => Prerequisite::RRsetDoesNotExist { name, rtype },
+                Some(c) if c == class => {
+                    // Consecutive records with the same name and type make up one RRset
+                    if let Some(Prerequisite::RRsetEquals { records }) = prerequisites.last_mut() {
+                        if records[0].rtype() == rtype && records[0].name().is_some_and(|n| n.eq_ignore_case(&name)) {
+                            records.push(rr.clone());
+                            continue;
+                        }
+                    }
+                    Prerequisite::RRsetEquals { records: vec![rr.clone()] }
+                }
+                _ => return Err(UpdateError::FormatError("invalid prerequisite class")),
+            };
+            prerequisites.push(prerequisite);
+        }
+
+        let mut updates = Vec::with_capacity(msg.authorities.len());
+        for rr in msg.authorities.iter() {
+            let name = rr.name().ok_or(UpdateError::FormatError("unexpected OPT record"))?.clone();
+            let rtype = rr.rtype();
+            let empty = matches!(rr, ResourceRecord::Unknown { data, .. } if data.is_empty());
+            // RFC 2136 §2.5.2-2.5.4
+            if matches!(rr.class(), Some(Class::Any | Class::None)) && rr.ttl() != Some(Ttl::ZERO) {
+                return Err(UpdateError::FormatError("deletion TTL must be zero"));
+            }
+            let update = match rr.class() {
+                Some(Class::Any) if empty && rtype == TYPE_ANY => UpdateOp::DeleteAll { name },
+                Some(Class::Any) if empty => UpdateOp::DeleteRRset { name, rtype },
+                Some(Class::None) if rtype != TYPE_ANY => UpdateOp::DeleteRecord(rr.with_class_and_ttl(class, Ttl::ZERO)),
+                Some(c) if c == class && rtype != TYPE_ANY => UpdateOp::Add(rr.clone()),
+                _ => return Err(UpdateError::FormatError("invalid update class")),
+            };
+            updates.push(update);
+        }
+
+        Ok(Update {
+            id: msg.id(),
+            zone: zone.qname.clone(),
+            class,
+            prerequisites,
+            updates,
+            additionals: msg.additionals.clone(),
+        })
+    }
+}
+
+/// Builds the response to an update request, applying it to `zone` if it is valid.
+pub fn handle_update(zone: &mut Zone, request: &Message) -> Message {
+    let rcode = match Update::from_message(request).and_then(|update| zone.apply_update(&update)) {
+        Ok(()) => Rcode::NoError,
+        Err(e) => e.rcode(),
+    };
+    let mut response = Message::response(request.clone(), false);
+    response.header.rcode = rcode;
+    response
+}
+
+/// An update RR with no RDATA, used to match or delete whole RRsets.
+fn empty_rr(name: &Name, rtype: Type, class: Class) -> ResourceRecord {
+    ResourceRecord::Unknown {
+        name: name.clone(),
+        rtype,
+        class,
+        ttl: Ttl::ZERO,
+        data: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn a(name: &str, addr: [u8; 4]) -> ResourceRecord {
+        ResourceRecord::A {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(300),
+            addr: Ipv4Addr::from(addr),
+        }
+    }
+
+    #[test]
+    fn update_round_trips_through_message() {
+        let mut update = Update::new(7, "example.com.".parse().unwrap());
+        update.prerequisites = vec![
+            Prerequisite::NameNotInUse { name: "new.example.com.".parse().unwrap() },
+            Prerequisite::RRsetExists { name: "example.com.".parse().unwrap(), rtype: Type::NS },
+            Prerequisite::RRsetEquals {
+                records: vec![a("www.example.com.", [192, 0, 2, 1]), a("www.example.com.", [192, 0, 2, 2])],
+            },
+        ];
+        update.updates = vec![
+            UpdateOp::Add(a("new.example.com.", [192, 0, 2, 3])),
+            UpdateOp::DeleteRRset { name: "old.example.com.".parse().unwrap(), rtype: Type::A },
+            UpdateOp::DeleteAll { name: "gone.example.com.".parse().unwrap() },
+            UpdateOp::DeleteRecord(a("www.example.com.", [192, 0, 2, 2])),
+        ];
+
+        let msg = Message::decode(&update.to_message().encode()).unwrap();
+        assert_eq!(msg.opcode(), Opcode::Update);
+        let mut decoded = Update::from_message(&msg).unwrap();
+        // TTLs are not carried by value-dependent prerequisites or deletions
+        if let Prerequisite::RRsetEquals { records } = &mut decoded.prerequisites[2] {
+            *records = records.iter().map(|rr| rr.with_class_and_ttl(Class::Internet, Ttl::from_secs(300))).collect();
+        }
+        if let UpdateOp::DeleteRecord(rr) = &mut decoded.updates[3] {
+            *rr = rr.with_class_and_ttl(Class::Internet, Ttl::from_secs(300));
+        }
+        assert_eq!(decoded, update);
+    }
+
+    #[test]
+    fn rejects_non_update_messages() {
+        let query = Message::query(1, false, Question::new("example.com.", QType::ByType(Type::SOA)).unwrap());
+        assert_eq!(Update::from_message(&query), Err(UpdateError::FormatError("opcode is not UPDATE")));
+    }
+
+    #[test]
+    fn rejects_deletions_with_a_ttl() {
+        let deletions = [
+            UpdateOp::DeleteRRset { name: "old.example.com.".parse().unwrap(), rtype: Type::A },
+            UpdateOp::DeleteAll { name: "gone.example.com.".parse().unwrap() },
+            UpdateOp::DeleteRecord(a("www.example.com.", [192, 0, 2, 2])),
+        ];
+        for deletion in deletions {
+            let mut update = Update::new(7, "example.com.".parse().unwrap());
+            update.updates = vec![deletion];
+            let mut msg = update.to_message();
+            let rr = &msg.authorities[0];
+            msg.authorities[0] = rr.with_class_and_ttl(rr.class().unwrap(), Ttl::from_secs(300));
+            let msg = Message::decode(&msg.encode()).unwrap();
+            let error = Update::from_message(&msg).unwrap_err();
+            assert_eq!(error, UpdateError::FormatError("deletion TTL must be zero"));
+            assert_eq!(error.rcode(), Rcode::FormatError);
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! In-memory storage for the records of a single zone.
+
+use crate::header::Rcode;
+use crate::message::Message;
+use crate::names::Name;
+use crate::question::QType;
+use crate::rr::{Class, ResourceRecord, Type};
+use crate::serial::Serial;
+use crate::ttl::Ttl;
+use crate::update::{Prerequisite, Update, UpdateError, UpdateOp};
+use std::cmp::Ordering;
+use thiserror::Error;
+
+/// The records making up a zone of authority, rooted at the owner of its `SOA` record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    origin: Name,
+    class: Class,
+    records: Vec<ResourceRecord>,
+}
+
+/// Errors that may occur while building a `Zone`
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ZoneError {
+    #[error("a zone must contain exactly one SOA record")]
+    MissingSoa,
+
+    #[error("{0} is not within the zone")]
+    OutOfZone(Name),
+}
+
+impl Zone {
+    /// Creates a `Zone` from its records, one of which must be the `SOA` at the zone apex.
+    pub fn from_records(records: Vec<ResourceRecord>) -> Result<Zone, ZoneError> {
+        let mut soas = records.iter().filter(|rr| rr.rtype() == Type::SOA);
+        let (origin, class) = match (soas.next(), soas.next()) {
+            (Some(soa), None) => (soa.name().unwrap().clone(), soa.class().unwrap()),
+            _ => return Err(ZoneError::MissingSoa),
+        };
+        for rr in records.iter() {
+            match rr.name() {
+                Some(name) if name.is_subdomain_of(&origin) => {}
+                Some(name) => return Err(ZoneError::OutOfZone(name.clone())),
+                None => return Err(ZoneError::OutOfZone(Name { name: vec![0] })),
+            }
+        }
+        Ok(Zone { origin, class, records })
+    }
+
+    /// The name of the zone apex.
+    pub fn origin(&self) -> &Name {
+        &self.origin
+    }
+
+    /// The class of the zone.
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    /// All records in the zone.
+    pub fn records(&self) -> &[ResourceRecord] {
+        &self.records
+    }
+
+    /// The `SOA` record at the zone apex.
+    pub fn soa(&self) -> &ResourceRecord {
+        self.records.iter()
+            .find(|rr| rr.rtype() == Type::SOA)
+            .expect("zones always hold an SOA record")
+    }
+
+    /// The serial number of the zone's `SOA` record.
+    pub fn serial(&self) -> Serial {
+        match self.soa() {
+            ResourceRecord::SOA { serial, .. } => *serial,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the records with the given owner name and type.
+    pub fn rrset(&self, name: &Name, rtype: Type) -> Vec<&ResourceRecord> {
+        self.records.iter()
+            .filter(|rr| rr.rtype() == rtype && owned_by(rr, name))
+            .collect()
+    }
+
+    /// Determines whether any record is owned by `name`.
+    pub fn name_in_use(&self, name: &Name) -> bool {
+        name_in_use(&self.records, name)
+    }
+
+    /// Answers a standard query as an authoritative server for this zone would.
+    ///
+    /// Names below a zone cut are answered with a referral to the delegated servers, along with
+    /// any glue addresses held in this zone. Queries outside the zone are refused.
+    pub fn respond(&self, query: &Message) -> Message {
+        let mut response = Message::response(query.clone(), false);
+        let question = match query.questions.as_slice() {
+            [q] if q.qclass == self.class && q.qname.is_subdomain_of(&self.origin) => q,
+            [_] => {
+                response.header.rcode = Rcode::Refused;
+                return response;
+            }
+            _ => {
+                response.header.rcode = Rcode::FormatError;
+                return response;
+            }
+        };
+
+        if let Some(cut) = self.zone_cut(&question.qname) {
+            let ns: Vec<_> = self.rrset(&cut, Type::NS).into_iter().cloned().collect();
+            for rr in ns.iter() {
+                if let ResourceRecord::NS { ns_name, .. } = rr {
+                    response.additionals.extend(self.records.iter()
+                        .filter(|r| matches!(r.rtype(), Type::A | Type::AAAA) && owned_by(r, ns_name))
+                        .cloned());
+                }
+            }
+            response.authorities = ns;
+        } else {
+            response.header.authoritative = true;
+            let mut qname = question.qname.clone();
+            // Follow CNAMEs and DNAMEs as long as they stay within the zone
+            for _ in 0..8 {
+                let records: Vec<_> = rrsets_at(&self.records, &qname)
+                    .filter(|rr| match question.qtype {
+                        QType::ByType(t) => rr.rtype() == t,
+                        _ => true,
+                    })
+                    .cloned()
+                    .collect();
+                if !records.is_empty() {
+                    // Save a round trip by including the addresses of name servers
+                    for rr in records.iter() {
+                        if let ResourceRecord::NS { ns_name, .. } = rr {
+                            response.additionals.extend(self.records.iter()
+                                .filter(|r| matches!(r.rtype(), Type::A | Type::AAAA) && owned_by(r, ns_name))
+                                .cloned());
+                        }
+                    }
+                    response.answers.extend(records);
+                    break;
+                }
+                let cname = rrset(&self.records, &qname, Type::CNAME).next();
+                let target = match cname {
+                    Some(cname @ ResourceRecord::CNAME { cname: target, .. }) => {
+                        response.answers.push(cname.clone());
+                        Some(target.clone())
+                    }
+                    _ => match self.dname_above(&qname) {
+                        Some(dname @ ResourceRecord::DNAME { name, class, ttl, target }) => {
+                            let synthesized = match qname.replace_suffix(name, target) {
+                                Some(synthesized) => synthesized,
+                                None => {
+                                    response.header.rcode = Rcode::YXDomain;
+                                    break;
+                                }
+                            };
+                            response.answers.push(dname.clone());
+                            response.answers.push(ResourceRecord::CNAME {
+                                name: qname.clone(),
+                                class: *class,
+                                ttl: *ttl,
+                                cname: synthesized.clone(),
+                            });
+                            Some(synthesized)
+                        }
+                        _ => None,
+                    },
+                };
+                match target {
+                    Some(target) => {
+                        if !target.is_subdomain_of(&self.origin) || self.zone_cut(&target).is_some() {
+                            break;
+                        }
+                        qname = target;
+                    }
+                    None => {
+                        let exists = self.records.iter()
+                            .any(|rr| rr.name().is_some_and(|n| n.is_subdomain_of(&qname)));
+                        if !exists {
+                            response.header.rcode = Rcode::NameError;
+                        }
+                        response.authorities.push(self.soa().clone());
+                        break;
+                    }
+                }
+            }
+        }
+        response.header.answer_count = response.answers.len() as u16;
+        response.header.ns_count = response.authorities.len() as u16;
+        response.header.additional_count = response.additionals.len() as u16;
+        response
+    }
+
+    /// Finds a `DNAME` record owned by an ancestor of `name` within the zone.
+    fn dname_above(&self, name: &Name) -> Option<&ResourceRecord> {
+        let mut current = name.parent()?;
+        while current.is_subdomain_of(&self.origin) {
+            let dname = self.records.iter().find(|rr| rr.rtype() == Type::DNAME && owned_by(rr, &current));
+            if dname.is_some() {
+                return dname;
+            }
+            current = current.parent()?;
+        }
+        None
+    }
+
+    /// Finds the topmost delegation below the apex on the path to `name`.
+    fn zone_cut(&self, name: &Name) -> Option<Name> {
+        let mut cut = None;
+        let mut current = name.clone();
+        while !current.eq_ignore_case(&self.origin) {
+            if rrset(&self.records, &current, Type::NS).next().is_some() {
+                cut = Some(current.clone());
+            }
+            current = current.parent()?;
+        }
+        cut
+    }
+
+    /// Checks the prerequisites of `update` and, if they all hold, applies its changes.
+    ///
+    /// The update is atomic: if any check fails, the zone is left unchanged. When the update
+    /// changes the zone without itself increasing the `SOA` serial, the serial is incremented.
+    pub fn apply_update(&mut self, update: &Update) -> Result<(), UpdateError> {
+        if !update.zone.eq_ignore_case(&self.origin) || update.class != self.class {
+            return Err(UpdateError::NotAuth(update.zone.clone()));
+        }
+        for p in update.prerequisites.iter() {
+            self.check_prerequisite(p)?;
+        }
+        for u in update.updates.iter() {
+            let name = match u {
+                UpdateOp::Add(rr) | UpdateOp::DeleteRecord(rr) =>
+                    rr.name().ok_or(UpdateError::FormatError("unexpected OPT record"))?,
+                UpdateOp::DeleteRRset { name, .. } | UpdateOp::DeleteAll { name } => name,
+            };
+            if !name.is_subdomain_of(&self.origin) {
+                return Err(UpdateError::NotZone(name.clone()));
+            }
+        }
+
+        let mut records = self.records.clone();
+        for u in update.updates.iter() {
+            self.apply_op(&mut records, u);
+        }
+        if records == self.records {
+            return Ok(());
+        }
+        let old_serial = self.serial();
+        let new_serial = Zone::serial_of(&records);
+        if new_serial.partial_cmp(&old_serial) != Some(Ordering::Greater) {
+            for rr in records.iter_mut() {
+                if let ResourceRecord::SOA { serial, .. } = rr {
+                    *serial = old_serial.increment();
+                }
+            }
+        }
+        self.records = records;
+        Ok(())
+    }
+
+    fn check_prerequisite(&self, prerequisite: &Prerequisite) -> Result<(), UpdateError> {
+        let name = match prerequisite {
+            Prerequisite::RRsetExists { name, .. } |
+            Prerequisite::RRsetDoesNotExist { name, .. } |
+            Prerequisite::NameInUse { name } |
+            Prerequisite::NameNotInUse { name } => name,
+            Prerequisite::RRsetEquals { records } => records.first()
+                .and_then(|rr| rr.name())
+                .ok_or(UpdateError::FormatError("empty RRset prerequisite"))?,
+        };
+        if !name.is_subdomain_of(&self.origin) {
+            return Err(UpdateError::NotZone(name.clone()));
+        }
+        match prerequisite {
+            Prerequisite::RRsetExists { name, rtype } => {
+                if rrset(&self.records, name, *rtype).next().is_none() {
+                    return Err(UpdateError::RRsetDoesNotExist(name.clone(), *rtype));
+                }
+            }
+            Prerequisite::RRsetDoesNotExist { name, rtype } => {
+                if rrset(&self.records, name, *rtype).next().is_some() {
+                    return Err(UpdateError::RRsetExists(name.clone(), *rtype));
+                }
+            }
+            Prerequisite::NameInUse { name } => {
+                if !name_in_use(&self.records, name) {
+                    return Err(UpdateError::NameNotInUse(name.clone()));
+                }
+            }
+            Prerequisite::NameNotInUse { name } => {
+                if name_in_use(&self.records, name) {
+                    return Err(UpdateError::NameInUse(name.clone()));
+                }
+            }
+            Prerequisite::RRsetEquals { records } => {
+                let rtype = records[0].rtype();
+                let existing: Vec<_> = rrset(&self.records, name, rtype).collect();
+                // Compared as sets, so repeating a record in the prerequisite changes nothing
+                let matches = records.iter().all(|rr| existing.iter().any(|e| same_data(e, rr))) &&
+                    existing.iter().all(|e| records.iter().any(|rr| same_data(e, rr)));
+                if !matches {
+                    return Err(UpdateError::RRsetDoesNotExist(name.clone(), rtype));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_op(&self, records: &mut Vec<ResourceRecord>, op: &UpdateOp) {
+        match op {
+            UpdateOp::Add(rr) => {
+                let name = rr.name().unwrap();
+                let at_apex = name.eq_ignore_case(&self.origin);
+                let rr = rr.with_class_and_ttl(self.class, rr.ttl().unwrap_or(Ttl::ZERO));
+                match rr.rtype() {
+                    Type::SOA => {
+                        // The SOA is only ever replaced, and only by one with a newer serial
+                        let newer = match rr {
+                            ResourceRecord::SOA { serial, .. } => serial > Zone::serial_of(records),
+                            _ => false,
+                        };
+                        if at_apex && newer {
+                            records.retain(|r| r.rtype() != Type::SOA);
+                            records.push(rr);
+                        }
+                        return;
+                    }
+                    Type::CNAME => {
+                        if rrsets_at(records, name).any(|r| r.rtype() != Type::CNAME) {
+                            return;
+                        }
+                        records.retain(|r| !(r.rtype() == Type::CNAME && owned_by(r, name)));
+                    }
+                    _ => {
+                        if rrset(records, name, Type::CNAME).next().is_some() {
+                            return;
+                        }
+                        records.retain(|r| !same_data(r, &rr));
+                    }
+                }
+                records.push(rr);
+            }
+            UpdateOp::DeleteRRset { name, rtype } => {
+                let at_apex = name.eq_ignore_case(&self.origin);
+                if *rtype == Type::SOA || (at_apex && *rtype == Type::NS) {
+                    return;
+                }
+                records.retain(|r| !(r.rtype() == *rtype && owned_by(r, name)));
+            }
+            UpdateOp::DeleteAll { name } => {
+                let at_apex = name.eq_ignore_case(&self.origin);
+                records.retain(|r| {
+                    !owned_by(r, name) || (at_apex && (r.rtype() == Type::SOA || r.rtype() == Type::NS))
+                });
+            }
+            UpdateOp::DeleteRecord(rr) => {
+                let name = rr.name().unwrap();
+                let rtype = rr.rtype();
+                if rtype == Type::SOA {
+                    return;
+                }
+                // The last NS record at the apex is never removed
+                if rtype == Type::NS && name.eq_ignore_case(&self.origin) &&
+                    rrset(records, name, Type::NS).filter(|r| !same_data(r, rr)).count() == 0 {
+                    return;
+                }
+                records.retain(|r| !same_data(r, rr));
+            }
+        }
+    }
+
+    fn serial_of(records: &[ResourceRecord]) -> Serial {
+        records.iter()
+            .find_map(|rr| match rr {
+                ResourceRecord::SOA { serial, .. } => Some(*serial),
+                _ => None,
+            })
+            .expect("zones always hold an SOA record")
+    }
+}
+
+fn owned_by(rr: &ResourceRecord, name: &Name) -> bool {
+    rr.name().is_some_and(|n| n.eq_ignore_case(name))
+}
+
+fn rrsets_at<'a>(records: &'a [ResourceRecord], name: &'a Name) -> impl Iterator<Item=&'a ResourceRecord> {
+    records.iter().filter(move |rr| owned_by(rr, name))
+}
+
+fn rrset<'a>(records: &'a [ResourceRecord], name: &'a Name, rtype: Type) -> impl Iterator<Item=&'a ResourceRecord> {
+    rrsets_at(records, name).filter(move |rr| rr.rtype() == rtype)
+}
+
+fn name_in_use(records: &[ResourceRecord], name: &Name) -> bool {
+    rrsets_at(records, name).next().is_some()
+}
+
+/// Compares two records by owner, type and data, ignoring class, TTL and the case of the owner.
+fn same_data(a: &ResourceRecord, b: &ResourceRecord) -> bool {
+    let normalize = |rr: &ResourceRecord| {
+        let mut rr = rr.with_class_and_ttl(Class::Any, Ttl::ZERO);
+        if let Some(name) = rr.name_mut() {
+            *name = name.to_lowercase();
+        }
+        rr
+    };
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn a(name: &str, addr: [u8; 4]) -> ResourceRecord {
+        ResourceRecord::A {
+            name: name.parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(300),
+            addr: Ipv4Addr::from(addr),
+        }
+    }
+
+    fn example_zone() -> Zone {
+        Zone::from_records(vec![
+            ResourceRecord::SOA {
+                name: "example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(3600),
+                mname: "ns1.example.com.".parse().unwrap(),
+                rname: "hostmaster.example.com.".parse().unwrap(),
+                serial: Serial(10),
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+            ResourceRecord::NS {
+                name: "example.com.".parse().unwrap(),
+                class: Class::Internet,
+                ttl: Ttl::from_secs(3600),
+                ns_name: "ns1.example.com.".parse().unwrap(),
+            },
+            a("ns1.example.com.", [192, 0, 2, 53]),
+            a("www.example.com.", [192, 0, 2, 1]),
+        ]).unwrap()
+    }
+
+    fn update(prerequisites: Vec<Prerequisite>, updates: Vec<UpdateOp>) -> Update {
+        let mut update = Update::new(1, "example.com.".parse().unwrap());
+        update.prerequisites = prerequisites;
+        update.updates = updates;
+        update
+    }
+
+    #[test]
+    fn add_record_bumps_serial() {
+        let mut zone = example_zone();
+        let www = "www.example.com.".parse().unwrap();
+        zone.apply_update(&update(vec![], vec![UpdateOp::Add(a("www.example.com.", [192, 0, 2, 2]))])).unwrap();
+        assert_eq!(zone.rrset(&www, Type::A).len(), 2);
+        assert_eq!(zone.serial(), Serial(11));
+    }
+
+    #[test]
+    fn failed_prerequisite_leaves_zone_unchanged() {
+        let mut zone = example_zone();
+        let before = zone.clone();
+        let result = zone.apply_update(&update(
+            vec![Prerequisite::NameNotInUse { name: "www.example.com.".parse().unwrap() }],
+            vec![UpdateOp::Add(a("www.example.com.", [192, 0, 2, 2]))],
+        ));
+        assert_eq!(result, Err(UpdateError::NameInUse("www.example.com.".parse().unwrap())));
+        assert_eq!(result.unwrap_err().rcode(), crate::header::Rcode::YXDomain);
+        assert_eq!(zone, before);
+    }
+
+    #[test]
+    fn rrset_equals_ignores_ttl() {
+        let mut zone = example_zone();
+        let www = a("www.example.com.", [192, 0, 2, 1]).with_class_and_ttl(Class::Internet, Ttl::ZERO);
+        zone.apply_update(&update(
+            vec![Prerequisite::RRsetEquals { records: vec![www.clone()] }],
+            vec![UpdateOp::DeleteRecord(www)],
+        )).unwrap();
+        assert!(!zone.name_in_use(&"www.example.com.".parse().unwrap()));
+    }
+
+    #[test]
+    fn rrset_equals_compares_sets() {
+        let mut zone = example_zone();
+        zone.apply_update(&update(vec![], vec![UpdateOp::Add(a("www.example.com.", [192, 0, 2, 2]))])).unwrap();
+        let www1 = a("www.example.com.", [192, 0, 2, 1]);
+        let result = zone.apply_update(&update(
+            vec![Prerequisite::RRsetEquals { records: vec![www1.clone(), www1.clone()] }],
+            vec![],
+        ));
+        assert_eq!(result, Err(UpdateError::RRsetDoesNotExist("www.example.com.".parse().unwrap(), Type::A)));
+        zone.apply_update(&update(
+            vec![Prerequisite::RRsetEquals { records: vec![www1.clone(), a("www.example.com.", [192, 0, 2, 2]), www1] }],
+            vec![],
+        )).unwrap();
+    }
+
+    #[test]
+    fn apex_soa_and_ns_survive_delete_all() {
+        let mut zone = example_zone();
+        let apex: Name = "example.com.".parse().unwrap();
+        zone.apply_update(&update(vec![], vec![
+            UpdateOp::DeleteAll { name: apex.clone() },
+            UpdateOp::DeleteRRset { name: apex.clone(), rtype: Type::NS },
+        ])).unwrap();
+        assert_eq!(zone.rrset(&apex, Type::NS).len(), 1);
+        assert_eq!(zone.serial(), Serial(10));
+    }
+
+    #[test]
+    fn names_outside_zone_are_rejected() {
+        let mut zone = example_zone();
+        let result = zone.apply_update(&update(vec![], vec![UpdateOp::Add(a("www.example.org.", [192, 0, 2, 1]))]));
+        assert_eq!(result, Err(UpdateError::NotZone("www.example.org.".parse().unwrap())));
+
+        let mut other = update(vec![], vec![]);
+        other.zone = "example.org.".parse().unwrap();
+        assert_eq!(zone.apply_update(&other).unwrap_err().rcode(), crate::header::Rcode::NotAuth);
+    }
+
+    #[test]
+    fn respond_answers_refers_and_denies() {
+        let mut zone = example_zone();
+        zone.records.push(ResourceRecord::NS {
+            name: "sub.example.com.".parse().unwrap(),
+            class: Class::Internet,
+            ttl: Ttl::from_secs(3600),
+            ns_name: "ns.sub.example.com.".parse().unwrap(),
+        });
+        zone.records.push(a("ns.sub.example.com.", [192, 0, 2, 54]));
+        let query = |name: &str| Message::query(7, false, crate::question::Question::new(name, QType::ByType(Type::A)).unwrap());
+
+        let answer = zone.respond(&query("www.example.com."));
+        assert!(answer.authoritative());
+        assert_eq!(answer.answers, vec![a("www.example.com.", [192, 0, 2, 1])]);
+
+        let referral = zone.respond(&query("host.sub.example.com."));
+        assert!(!referral.authoritative());
+        assert_eq!(referral.authorities[0].rtype(), Type::NS);
+        assert_eq!(referral.additionals, vec![a("ns.sub.example.com.", [192, 0, 2, 54])]);
+
+        let missing = zone.respond(&query("missing.example.com."));
+        assert_eq!(missing.rcode(), Rcode::NameError);
+        assert_eq!(missing.authorities, vec![zone.soa().clone()]);
+
+        assert_eq!(zone.respond(&query("www.example.org.")).rcode(), Rcode::Refused);
+    }
+}